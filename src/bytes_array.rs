@@ -0,0 +1,94 @@
+/// `#[serde(with = "bencode::bytes_array")]` to keep `[u8; N]` a byte string
+use std::fmt;
+
+use serde::{de, Deserializer, Serializer};
+
+/// serde hands fixed size arrays over the same way as tuples, which would
+/// make a list of ints out of a hash
+pub fn serialize<S: Serializer, const N: usize>(
+    bytes: &[u8; N],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+/// bencode only has the byte string form, other formats may hand over a
+/// sequence of bytes instead
+pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+    deserializer: D,
+) -> Result<[u8; N], D::Error> {
+    deserializer.deserialize_bytes(ArrayVisitor)
+}
+
+struct ArrayVisitor<const N: usize>;
+
+impl<'de, const N: usize> de::Visitor<'de> for ArrayVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a {} byte string", N)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<[u8; N], E> {
+        if v.len() != N {
+            return Err(E::invalid_length(v.len(), &self));
+        }
+        let mut array = [0; N];
+        array.copy_from_slice(v);
+        Ok(array)
+    }
+
+    // serde's buffered path hands over byte strings that are valid utf-8 as
+    // `str`
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<[u8; N], E> {
+        self.visit_bytes(v.as_bytes())
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<[u8; N], A::Error> {
+        let mut array = [0; N];
+        for (i, byte) in array.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+        }
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(N + 1, &self));
+        }
+        Ok(array)
+    }
+}
+
+#[test]
+fn test_bytes_array() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        #[serde(with = "crate::bytes_array")]
+        h: [u8; 4],
+        list: [u8; 2],
+    }
+    let data = b"d1:h4:abcd4:listli1ei2eee";
+    let x: X = crate::decode::from_bytes(data).unwrap();
+    assert_eq!(x.h, *b"abcd");
+    assert_eq!(crate::encode::to_bytes(&x).unwrap(), data);
+
+    // the same through serde's buffered path, and from a reader
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(tag = "t")]
+    enum Tagged {
+        X(X),
+    }
+    let tagged: Tagged =
+        crate::decode::from_bytes(b"d1:h4:\xff\x00cd4:listli1ei2ee1:t1:Xe").unwrap();
+    assert_eq!(
+        tagged,
+        Tagged::X(X {
+            h: *b"\xff\x00cd",
+            list: [1, 2]
+        })
+    );
+    assert_eq!(crate::decode::from_reader::<_, X>(&data[..]).unwrap(), x);
+
+    let err = crate::decode::from_bytes::<X>(b"d1:h3:abc4:listli1ei2eee").unwrap_err();
+    assert_eq!(err.path_string(), "h");
+    assert_eq!(err.offset(), Some(4));
+}
@@ -1,105 +1,400 @@
 /// functionality for decoding bencoded byte strings
-use nom::{
-    bytes::complete::{tag, take_until1},
-    character::complete::{digit1, u64},
-    combinator::map_parser,
-    multi::length_data,
-    sequence::{delimited,terminated},
-    IResult,
-};
+use std::{io, marker::PhantomData};
+
 use serde::{
-    de::{self, EnumAccess, IntoDeserializer, VariantAccess},
-    Deserialize,
+    de::{
+        self, value::BorrowedStrDeserializer, DeserializeOwned, EnumAccess, IntoDeserializer,
+        VariantAccess,
+    },
+    forward_to_deserialize_any, Deserialize,
 };
 
-use crate::Error;
+use crate::{
+    raw,
+    read::{describe, IoRead, Read, Reference, SliceRead},
+    spanned, BoolRepr, Error, ErrorKind, PathSegment,
+};
 
-//
-// ------------------------------- NOM -------------------------------
-//
+/// `0`, or an optionally negative number without leading zeros
+fn canonical_int(body: &[u8]) -> bool {
+    match body.strip_prefix(b"-").unwrap_or(body) {
+        [b'0'] => body.len() == 1,
+        [b'1'..=b'9', rest @ ..] => rest.iter().all(u8::is_ascii_digit),
+        _ => false,
+    }
+}
 
-fn int<'a>(i: &'a [u8]) -> IResult<&'a [u8], u64> {
-    map_parser(delimited(tag("i"), take_until1("e"), tag("e")), u64)(i)
+/// an optionally negative run of digits. `FromStr` alone would also take a
+/// leading `+`, which isn't bencode in any mode
+fn parse_digits<T: std::str::FromStr>(digits: &[u8], kind: ErrorKind) -> Result<T, Error> {
+    let unsigned = digits.strip_prefix(b"-").unwrap_or(digits);
+    let valid = !unsigned.is_empty() && unsigned.iter().all(u8::is_ascii_digit);
+    std::str::from_utf8(digits)
+        .ok()
+        .filter(|_| valid)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| Error::new(kind).found(String::from_utf8_lossy(digits)))
 }
 
-fn str<'a>(i: &'a [u8]) -> IResult<&'a [u8], &'a [u8]> {
-    length_data(map_parser(terminated(digit1, tag(":")), u64))(i)
+fn utf8(bytes: &[u8]) -> Result<&str, Error> {
+    std::str::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidUtf8).found(e))
 }
 
 //
 // ------------------------------- SERDE -------------------------------
 //
 
-pub struct Deserializer<'de> {
-    input: &'de [u8],
-    pos: usize,
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// reject anything but the canonical encoding: integers and string lengths
+    /// without leading zeros, no `i-0e`, and dict keys sorted and unique.
+    /// bools must also use exactly the configured representation, where
+    /// lenient mode accepts either one and treats any nonzero integer as true
+    pub strict: bool,
+    pub bool_repr: BoolRepr,
 }
 
-impl<'de> Deserializer<'de> {
+/// how deeply lists and dicts may nest before decoding gives up, so hostile
+/// input like `llll...` can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+pub struct Deserializer<R> {
+    read: R,
+    scratch: Vec<u8>,
+    options: DecodeOptions,
+    remaining_depth: usize,
+}
+
+impl<'de> Deserializer<SliceRead<'de>> {
     pub fn from_bytes(input: &'de [u8]) -> Self {
-        Deserializer { input, pos: 0 }
+        Self::from_bytes_with_options(input, DecodeOptions::default())
+    }
+
+    pub fn from_bytes_with_options(input: &'de [u8], options: DecodeOptions) -> Self {
+        Deserializer::new(SliceRead::new(input), options)
+    }
+}
+
+impl<R: io::Read> Deserializer<IoRead<R>> {
+    pub fn from_reader(reader: R) -> Self {
+        Self::from_reader_with_options(reader, DecodeOptions::default())
+    }
+
+    pub fn from_reader_with_options(reader: R, options: DecodeOptions) -> Self {
+        Deserializer::new(IoRead::new(reader), options)
+    }
+}
+
+impl<'de, R: Read<'de>> Deserializer<R> {
+    pub fn new(read: R, options: DecodeOptions) -> Self {
+        Deserializer {
+            read,
+            scratch: Vec::new(),
+            options,
+            remaining_depth: MAX_DEPTH,
+        }
+    }
+
+    /// checks that the whole input has been consumed
+    pub fn end(&mut self) -> Result<(), Error> {
+        match self.read.peek()? {
+            Some(byte) => Err(Error::new(ErrorKind::TrailingBytes)
+                .found(describe(byte))
+                .or_offset(self.read.position())),
+            None => Ok(()),
+        }
+    }
+
+    /// number of input bytes consumed so far
+    pub fn byte_offset(&self) -> usize {
+        self.read.position()
+    }
+
+    /// turns a sequence of back to back values into an iterator over them
+    pub fn into_stream<T: Deserialize<'de>>(self) -> StreamDeserializer<'de, R, T> {
+        StreamDeserializer {
+            de: self,
+            failed: false,
+            output: PhantomData,
+            lifetime: PhantomData,
+        }
+    }
+
+    fn peek(&mut self) -> Result<Option<u8>, Error> {
+        self.read.peek()
+    }
+
+    /// skips over the next value and hands its exact bytes to a `RawValue`
+    fn deserialize_raw<V>(&mut self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.read.begin_raw();
+        de::IgnoredAny::deserialize(&mut *self)?;
+        match self.read.end_raw(&mut self.scratch) {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
+    }
+
+    /// error for a token that doesn't start with what the caller expected
+    fn unexpected(&mut self, expected: &'static str) -> Error {
+        let error = match self.read.peek() {
+            Ok(Some(byte)) => Error::new(ErrorKind::UnexpectedToken).found(describe(byte)),
+            Ok(None) => Error::new(ErrorKind::UnexpectedEof),
+            Err(e) => e,
+        };
+        error.expected(expected).or_offset(self.read.position())
+    }
+
+    /// consumes the opening byte of a list or dict
+    fn enter(&mut self) -> Result<(), Error> {
+        if self.remaining_depth == 0 {
+            return Err(Error::new(ErrorKind::DepthLimitExceeded).or_offset(self.read.position()));
+        }
+        self.remaining_depth -= 1;
+        self.read.discard();
+        Ok(())
+    }
+
+    /// consumes the closing `e` of a list or dict
+    fn leave(&mut self, expected: &'static str) -> Result<(), Error> {
+        match self.peek()? {
+            Some(b'e') => {
+                self.read.discard();
+                self.remaining_depth += 1;
+                Ok(())
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn int_body(&mut self) -> Result<Reference<'de, '_>, Error> {
+        let start = self.read.position();
+        let strict = self.options.strict;
+        let body = self
+            .read
+            .parse_int(&mut self.scratch)
+            .map_err(|e| e.or_offset(start))?;
+        if strict && !canonical_int(&body) {
+            return Err(Error::new(ErrorKind::InvalidInteger)
+                .expected("canonical integer")
+                .found(String::from_utf8_lossy(&body))
+                .or_offset(start));
+        }
+        Ok(body)
+    }
+
+    fn parse_int<T: std::str::FromStr>(&mut self) -> Result<T, Error> {
+        let start = self.read.position();
+        parse_digits(&self.int_body()?, ErrorKind::InvalidInteger).map_err(|e| e.or_offset(start))
+    }
+
+    fn parse_str(&mut self) -> Result<Reference<'de, '_>, Error> {
+        let start = self.read.position();
+        let len = self
+            .read
+            .parse_str_len(&mut self.scratch)
+            .map_err(|e| e.or_offset(start))?;
+        if self.options.strict && len.len() > 1 && len[0] == b'0' {
+            return Err(Error::new(ErrorKind::InvalidLength)
+                .expected("length without leading zeros")
+                .found(String::from_utf8_lossy(&len))
+                .or_offset(start));
+        }
+        let len = parse_digits(&len, ErrorKind::InvalidLength).map_err(|e| e.or_offset(start))?;
+        self.read
+            .parse_bytes(len, &mut self.scratch)
+            .map_err(|e| e.or_offset(start))
     }
 }
 
+/// decodes a whole document, strings and byte strings borrow from `i` where
+/// the target type allows it and are copied otherwise, so any
+/// `DeserializeOwned` type works too
 pub fn from_bytes<'a, T: Deserialize<'a>>(i: &'a [u8]) -> Result<T, Error> {
-    let mut deserializer = Deserializer::from_bytes(i);
-    let t = T::deserialize(&mut deserializer)?;
-    if deserializer.input.len() <= deserializer.pos {
-        Ok(t)
-    } else {
-        Err(Error::Message("trailing bytes".to_string()))
+    from_bytes_with_options(i, DecodeOptions::default())
+}
+
+pub fn from_bytes_with_options<'a, T: Deserialize<'a>>(
+    i: &'a [u8],
+    options: DecodeOptions,
+) -> Result<T, Error> {
+    let mut deserializer = Deserializer::from_bytes_with_options(i, options);
+    let t = T::deserialize(&mut deserializer).map_err(|e| e.or_offset(0))?;
+    deserializer.end()?;
+    Ok(t)
+}
+
+/// decodes the value at the start of `i` and hands back whatever follows it,
+/// like the raw piece after a BEP 9 ut_metadata header
+pub fn from_bytes_prefix<'a, T: Deserialize<'a>>(i: &'a [u8]) -> Result<(T, &'a [u8]), Error> {
+    from_bytes_prefix_with_options(i, DecodeOptions::default())
+}
+
+pub fn from_bytes_prefix_with_options<'a, T: Deserialize<'a>>(
+    i: &'a [u8],
+    options: DecodeOptions,
+) -> Result<(T, &'a [u8]), Error> {
+    let mut deserializer = Deserializer::from_bytes_with_options(i, options);
+    let t = T::deserialize(&mut deserializer).map_err(|e| e.or_offset(0))?;
+    Ok((t, &i[deserializer.byte_offset()..]))
+}
+
+/// decodes a single value from a stream, reading it only as far as needed
+pub fn from_reader<R: io::Read, T: DeserializeOwned>(reader: R) -> Result<T, Error> {
+    from_reader_with_options(reader, DecodeOptions::default())
+}
+
+pub fn from_reader_with_options<R: io::Read, T: DeserializeOwned>(
+    reader: R,
+    options: DecodeOptions,
+) -> Result<T, Error> {
+    let mut deserializer = Deserializer::from_reader_with_options(reader, options);
+    let t = T::deserialize(&mut deserializer).map_err(|e| e.or_offset(0))?;
+    deserializer.end()?;
+    Ok(t)
+}
+
+/// yields each of several concatenated values in turn, stopping after the
+/// first error since there's no telling where the next value would start
+pub struct StreamDeserializer<'de, R, T> {
+    de: Deserializer<R>,
+    failed: bool,
+    output: PhantomData<T>,
+    lifetime: PhantomData<&'de ()>,
+}
+
+impl<'de, R: Read<'de>, T: Deserialize<'de>> StreamDeserializer<'de, R, T> {
+    pub fn new(read: R, options: DecodeOptions) -> Self {
+        Deserializer::new(read, options).into_stream()
+    }
+
+    /// where the next value starts, which is also where the last one ended
+    pub fn byte_offset(&self) -> usize {
+        self.de.byte_offset()
     }
 }
 
-impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+impl<'de, R: Read<'de>, T: Deserialize<'de>> Iterator for StreamDeserializer<'de, R, T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let start = self.de.byte_offset();
+        let res = match self.de.peek() {
+            Ok(None) => return None,
+            Ok(Some(_)) => T::deserialize(&mut self.de),
+            Err(e) => Err(e),
+        };
+        self.failed = res.is_err();
+        Some(res.map_err(|e| e.or_offset(start)))
+    }
+}
+
+impl<'de, R: Read<'de>> de::Deserializer<'de> for &mut Deserializer<R> {
     type Error = Error;
 
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.input[self.pos] {
-            b'i' => self.deserialize_u64(visitor),
-            b'0'..=b'9' => self.deserialize_bytes(visitor),
-            b'l' => self.deserialize_seq(visitor),
-            b'd' => self.deserialize_map(visitor),
-            _ => Err(Error::Message("no match on any".to_string())),
+        match self.peek()? {
+            Some(b'i') => {
+                let start = self.read.position();
+                let body = self.int_body()?;
+                let result = if body.first() == Some(&b'-') {
+                    parse_digits(&body, ErrorKind::InvalidInteger)
+                        .and_then(|v| visitor.visit_i64(v))
+                } else {
+                    parse_digits(&body, ErrorKind::InvalidInteger)
+                        .and_then(|v| visitor.visit_u64(v))
+                };
+                // the visitor may reject the value, `Value` does for anything
+                // above `i64::MAX`, and should point at the integer too
+                result.map_err(|e| e.or_offset(start))
+            }
+            Some(b'0'..=b'9') => self.deserialize_bytes(visitor),
+            Some(b'l') => self.deserialize_seq(visitor),
+            Some(b'd') => self.deserialize_map(visitor),
+            _ => Err(self.unexpected("value")),
         }
     }
 
-    fn deserialize_bool<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        let repr = self.options.bool_repr;
+        let strict = self.options.strict;
+        let start = self.read.position();
+        let invalid = |kind, found: &[u8]| {
+            Error::new(kind)
+                .expected("bool")
+                .found(String::from_utf8_lossy(found))
+                .or_offset(start)
+        };
+        match (repr, self.peek()?) {
+            (BoolRepr::Reject, _) => Err(Error::unsupported("bool")),
+            (_, Some(b'i')) if !strict || repr == BoolRepr::Int => {
+                let body = self.int_body()?;
+                match &*body {
+                    b"0" => visitor.visit_bool(false),
+                    b"1" => visitor.visit_bool(true),
+                    _ if strict => Err(invalid(ErrorKind::InvalidInteger, &body)),
+                    _ => visitor
+                        .visit_bool(parse_digits::<i64>(&body, ErrorKind::InvalidInteger)? != 0),
+                }
+            }
+            (_, Some(b'0'..=b'9')) if !strict || repr == BoolRepr::String => {
+                let bytes = self.parse_str()?;
+                match &*bytes {
+                    b"false" => visitor.visit_bool(false),
+                    b"true" => visitor.visit_bool(true),
+                    _ => Err(invalid(ErrorKind::UnexpectedToken, &bytes)),
+                }
+            }
+            _ => Err(self.unexpected("bool")),
+        }
     }
 
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_u64(visitor)
+        self.deserialize_i64(visitor)
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_u64(visitor)
+        self.deserialize_i64(visitor)
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_u64(visitor)
+        self.deserialize_i64(visitor)
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_u64(visitor)
+        let int = self.parse_int()?;
+        visitor.visit_i64(int)
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        let int = self.parse_int()?;
+        visitor.visit_i128(int)
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
@@ -127,122 +422,139 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = self.input.len();
-        let res = int(&self.input[self.pos..]).map_err(|e| Error::Message(e.to_string()))?;
-        self.pos = len - res.0.len();
-        visitor.visit_u64(res.1)
+        let int = self.parse_int()?;
+        visitor.visit_u64(int)
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        let int = self.parse_int()?;
+        visitor.visit_u128(int)
     }
 
     fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        Err(Error::unsupported("f32"))
     }
 
     fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        Err(Error::unsupported("f64"))
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        let start = self.read.position();
+        let bytes = self.parse_str()?;
+        let mut chars = utf8(&bytes)?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(Error::new(ErrorKind::InvalidLength)
+                .expected("single character")
+                .found(String::from_utf8_lossy(&bytes))
+                .or_offset(start)),
+        }
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = self.input.len();
-        let res = str(&self.input[self.pos..]).map_err(|e| Error::Message(e.to_string()))?;
-        self.pos = len - res.0.len();
-        let str = std::str::from_utf8(res.1).map_err(|e| Error::Message(e.to_string()))?;
-
-        visitor.visit_borrowed_str(str)
+        match self.parse_str()? {
+            Reference::Borrowed(bytes) => {
+                let str = utf8(bytes)?;
+                visitor.visit_borrowed_str(str)
+            }
+            Reference::Copied(bytes) => {
+                let str = utf8(bytes)?;
+                visitor.visit_str(str)
+            }
+        }
     }
 
-    fn deserialize_string<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        self.deserialize_str(visitor)
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = self.input.len();
-        let res = str(&self.input[self.pos..]).map_err(|e| Error::Message(e.to_string()))?;
-        self.pos = len - res.0.len();
-        visitor.visit_borrowed_bytes(res.1)
+        match self.parse_str()? {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        self.deserialize_bytes(visitor)
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_some(self)   
+        visitor.visit_some(self)
     }
 
     fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        Err(Error::unsupported("()"))
     }
 
     fn deserialize_unit_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         _visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Error::Unimplemented)
+        Err(Error::unsupported(name))
     }
 
     fn deserialize_newtype_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
+        if name == raw::TOKEN {
+            return self.deserialize_raw(visitor);
+        }
         visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_seq<V>(mut self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.input[self.pos] {
-            b'l' => {
-                self.pos += 1;
-                let seq = visitor.visit_seq(SeqMap::new(&mut self))?;
-                match self.input[self.pos] {
-                    b'e' => {
-                        self.pos += 1;
-                        Ok(seq)
-                    }
-                    _ => Err(Error::Message("seq end de error".to_string())),
-                }
+        match self.peek()? {
+            Some(b'l') => {
+                self.enter()?;
+                let seq = visitor.visit_seq(SeqMap::new(self))?;
+                self.leave("end of list")?;
+                Ok(seq)
             }
-            _ => Err(Error::Message("seq start de error".to_string())),
+            _ => Err(self.unexpected("list")),
         }
     }
 
@@ -265,35 +577,33 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
         self.deserialize_seq(visitor)
     }
 
-    fn deserialize_map<V>(mut self, visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.input[self.pos] {
-            b'd' => {
-                self.pos += 1;
-                let map = visitor.visit_map(SeqMap::new(&mut self))?;
-                match self.input[self.pos] {
-                    b'e' => {
-                        self.pos += 1;
-                        Ok(map)
-                    }
-                    _ => Err(Error::Message("map end de error".to_string())),
-                }
+        match self.peek()? {
+            Some(b'd') => {
+                self.enter()?;
+                let map = visitor.visit_map(SeqMap::new(self))?;
+                self.leave("end of dict")?;
+                Ok(map)
             }
-            _ => Err(Error::Message("map start de error".to_string())),
+            _ => Err(self.unexpected("dict")),
         }
     }
 
     fn deserialize_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         _fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: serde::de::Visitor<'de>,
     {
+        if name == spanned::NAME {
+            return visitor.visit_map(SpannedAccess { de: self, field: 0 });
+        }
         self.deserialize_map(visitor)
     }
 
@@ -306,26 +616,18 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     where
         V: serde::de::Visitor<'de>,
     {
-        match self.input[self.pos] {
-            b'0'..=b'9' => {
-                let len = self.input.len();
-                let res =
-                    str(&self.input[self.pos..]).map_err(|e| Error::Message(e.to_string()))?;
-                self.pos = len - res.0.len();
-                visitor.visit_enum(std::str::from_utf8(res.1).unwrap().into_deserializer())
+        match self.peek()? {
+            Some(b'0'..=b'9') => {
+                let bytes = self.parse_str()?;
+                visitor.visit_enum(utf8(&bytes)?.into_deserializer())
             }
-            b'd' => {
-                self.pos += 1;
+            Some(b'd') => {
+                self.enter()?;
                 let value = visitor.visit_enum(Enum::new(self))?;
-                match self.input[self.pos] {
-                    b'e' => {
-                        self.pos += 1;
-                        Ok(value)
-                    }
-                    _ => Err(Error::Message("enum end de error".to_string())),
-                }
+                self.leave("end of enum")?;
+                Ok(value)
             }
-            _ => Err(Error::Message("enum start de error".to_string())),
+            _ => Err(self.unexpected("enum")),
         }
     }
 
@@ -344,40 +646,122 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     }
 }
 
-struct SeqMap<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
+/// feeds a `Spanned` its start offset, the value itself and then its end
+/// offset, in that order, since the end is only known after decoding
+struct SpannedAccess<'a, R> {
+    de: &'a mut Deserializer<R>,
+    field: usize,
 }
 
-impl<'a, 'de> SeqMap<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>) -> Self {
-        Self { de }
+impl<'de, R: Read<'de>> de::MapAccess<'de> for SpannedAccess<'_, R> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        let key = match self.field {
+            0 => spanned::START,
+            1 => spanned::VALUE,
+            2 => spanned::END,
+            _ => return Ok(None),
+        };
+        seed.deserialize(BorrowedStrDeserializer::new(key))
+            .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        self.field += 1;
+        match self.field {
+            2 => seed.deserialize(&mut *self.de),
+            _ => seed.deserialize(self.de.byte_offset().into_deserializer()),
+        }
+    }
+}
+
+struct SeqMap<'a, R> {
+    de: &'a mut Deserializer<R>,
+    /// the current dict key, kept for strict ordering checks and error paths
+    key: Option<Vec<u8>>,
+    index: usize,
+}
+
+impl<'a, R> SeqMap<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
+        Self {
+            de,
+            key: None,
+            index: 0,
+        }
+    }
+
+    fn key_segment(&self) -> PathSegment {
+        PathSegment::Key(self.key.clone().unwrap_or_default())
     }
 }
 
-impl<'de, 'a> de::SeqAccess<'de> for SeqMap<'a, 'de> {
+/// in strict mode every key has to sort strictly after the previous one
+fn check_key(last: Option<&[u8]>, key: &[u8]) -> Result<(), Error> {
+    match last {
+        Some(last) if key == last => Err(Error::new(ErrorKind::DuplicateKey)),
+        Some(last) if key < last => Err(Error::new(ErrorKind::UnsortedKeys)),
+        _ => Ok(()),
+    }
+    .map_err(|e| e.found(String::from_utf8_lossy(key)))
+}
+
+impl<'de, R: Read<'de>> de::SeqAccess<'de> for SeqMap<'_, R> {
     type Error = Error;
 
     fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
     where
         T: de::DeserializeSeed<'de>,
     {
-        match self.de.input[self.de.pos] {
-            b'e' => Ok(None),
-            _ => seed.deserialize(&mut *self.de).map(Some),
+        match self.de.peek()? {
+            Some(b'e') => Ok(None),
+            _ => {
+                let start = self.de.read.position();
+                let index = self.index;
+                self.index += 1;
+                seed.deserialize(&mut *self.de)
+                    .map(Some)
+                    .map_err(|e| e.or_offset(start).within(PathSegment::Index(index)))
+            }
         }
     }
 }
 
-impl<'de, 'a> de::MapAccess<'de> for SeqMap<'a, 'de> {
+impl<'de, R: Read<'de>> de::MapAccess<'de> for SeqMap<'_, R> {
     type Error = Error;
 
     fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
     where
         K: de::DeserializeSeed<'de>,
     {
-        match self.de.input[self.de.pos] {
-            b'e' => Ok(None),
-            _ => seed.deserialize(&mut *self.de).map(Some),
+        match self.de.peek()? {
+            Some(b'e') => Ok(None),
+            Some(b'0'..=b'9') => {
+                let start = self.de.read.position();
+                let strict = self.de.options.strict;
+                let key = self.de.parse_str()?;
+                if strict {
+                    check_key(self.key.as_deref(), &key).map_err(|e| e.or_offset(start))?;
+                }
+                // reuses the previous key's allocation
+                let last = self.key.get_or_insert_with(Vec::new);
+                last.clear();
+                last.extend_from_slice(&key);
+                seed.deserialize(KeyDeserializer { key })
+                    .map(Some)
+                    .map_err(|e| e.or_offset(start).within(PathSegment::Key(last.clone())))
+            }
+            Some(byte) => Err(Error::new(ErrorKind::InvalidKey)
+                .found(describe(byte))
+                .or_offset(self.de.read.position())),
+            None => Err(self.de.unexpected("dict key")),
         }
     }
 
@@ -385,21 +769,119 @@ impl<'de, 'a> de::MapAccess<'de> for SeqMap<'a, 'de> {
     where
         V: de::DeserializeSeed<'de>,
     {
+        let start = self.de.read.position();
         seed.deserialize(&mut *self.de)
+            .map_err(|e| e.or_offset(start).within(self.key_segment()))
+    }
+}
+
+/// hands an already parsed dict key to the key's `Deserialize` impl
+struct KeyDeserializer<'de, 's> {
+    key: Reference<'de, 's>,
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer<'de, '_> {
+    type Error = Error;
+
+    /// keys that are valid utf-8 go out as `str`, which is what serde's
+    /// buffering for tagged enums and `flatten` matches field names against
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.key {
+            Reference::Borrowed(bytes) => match std::str::from_utf8(bytes) {
+                Ok(str) => visitor.visit_borrowed_str(str),
+                Err(_) => visitor.visit_borrowed_bytes(bytes),
+            },
+            Reference::Copied(bytes) => match std::str::from_utf8(bytes) {
+                Ok(str) => visitor.visit_str(str),
+                Err(_) => visitor.visit_bytes(bytes),
+            },
+        }
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.key {
+            Reference::Borrowed(bytes) => visitor.visit_borrowed_bytes(bytes),
+            Reference::Copied(bytes) => visitor.visit_bytes(bytes),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.key {
+            Reference::Borrowed(bytes) => {
+                let str = utf8(bytes)?;
+                visitor.visit_borrowed_str(str)
+            }
+            Reference::Copied(bytes) => {
+                let str = utf8(bytes)?;
+                visitor.visit_str(str)
+            }
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        let str = utf8(&self.key)?;
+        visitor.visit_enum(str.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char
+        option unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
     }
 }
 
-struct Enum<'a, 'de: 'a> {
-    de: &'a mut Deserializer<'de>,
+struct Enum<'a, R> {
+    de: &'a mut Deserializer<R>,
 }
 
-impl<'a, 'de> Enum<'a, 'de> {
-    fn new(de: &'a mut Deserializer<'de>) -> Self {
+impl<'a, R> Enum<'a, R> {
+    fn new(de: &'a mut Deserializer<R>) -> Self {
         Self { de }
     }
 }
 
-impl<'de, 'a> EnumAccess<'de> for Enum<'a, 'de> {
+impl<'de, R: Read<'de>> EnumAccess<'de> for Enum<'_, R> {
     type Error = Error;
     type Variant = Self;
 
@@ -411,11 +893,15 @@ impl<'de, 'a> EnumAccess<'de> for Enum<'a, 'de> {
     }
 }
 
-impl<'de, 'a> VariantAccess<'de> for Enum<'a, 'de> {
+impl<'de, R: Read<'de>> VariantAccess<'de> for Enum<'_, R> {
     type Error = Error;
 
+    /// unit variants are plain byte strings, a dict means the name came with
+    /// a value we have nowhere to put
     fn unit_variant(self) -> Result<(), Self::Error> {
-        Err(Error::Message("unit variant".to_string()))
+        Err(Error::new(ErrorKind::UnexpectedToken)
+            .expected("unit variant as a byte string")
+            .found("dict"))
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
@@ -444,6 +930,66 @@ impl<'de, 'a> VariantAccess<'de> for Enum<'a, 'de> {
     }
 }
 
+/// `deserialize_with` for `bool` fields inside internally tagged enums or
+/// structs with a `flatten` field, where serde buffers the value first and
+/// only hands over the `i1e` or `4:true` it saw instead of a bool
+pub fn bool_from_any<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    deserializer.deserialize_any(AnyBool)
+}
+
+/// `bool_from_any` for `Option<bool>`, pair it with `#[serde(default)]`
+pub fn option_bool_from_any<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    deserializer.deserialize_any(AnyBool).map(Some)
+}
+
+struct AnyBool;
+
+impl<'de> de::Visitor<'de> for AnyBool {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a bool as 0, 1, `true` or `false`")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<bool, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<bool, E> {
+        match v {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(E::invalid_value(de::Unexpected::Signed(v), &self)),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<bool, E> {
+        match v {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<bool, E> {
+        self.visit_bytes(v.as_bytes())
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<bool, E> {
+        match v {
+            b"false" => Ok(false),
+            b"true" => Ok(true),
+            _ => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+        }
+    }
+}
+
 //
 // ------------------------------- TESTS -------------------------------
 //
@@ -533,3 +1079,587 @@ fn test3() {
 
     assert_eq!(x, y)
 }
+
+#[test]
+fn test_signed() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        a: i8,
+        b: i16,
+        c: i32,
+        d: i64,
+        e: i128,
+        f: u8,
+        g: u16,
+        h: u32,
+        i: u128,
+    }
+    let data = b"d1:ai-128e1:bi-3e1:ci0e1:di-9223372036854775808e1:ei-170141183460469231731687303715884105728e1:fi255e1:gi65535e1:hi4294967295e1:ii340282366920938463463374607431768211455ee";
+
+    let x = from_bytes::<X>(data).unwrap();
+    let y = X {
+        a: i8::MIN,
+        b: -3,
+        c: 0,
+        d: i64::MIN,
+        e: i128::MIN,
+        f: u8::MAX,
+        g: u16::MAX,
+        h: u32::MAX,
+        i: u128::MAX,
+    };
+
+    assert_eq!(x, y);
+    assert!(from_bytes::<u64>(b"i-1e").is_err());
+    assert!(from_bytes::<u8>(b"i256e").is_err());
+    assert!(from_bytes::<i8>(b"i-129e").is_err());
+}
+
+#[test]
+fn test_strict() {
+    let strict = DecodeOptions {
+        strict: true,
+        ..DecodeOptions::default()
+    };
+    let non_canonical: &[&[u8]] = &[
+        b"i03e",
+        b"i-0e",
+        b"i-03e",
+        b"03:abc",
+        b"d1:bi1e1:ai2ee",
+        b"d1:ai1e1:ai2ee",
+    ];
+    for data in non_canonical {
+        assert!(from_bytes_with_options::<crate::Value>(data, strict).is_err());
+        assert!(from_bytes::<crate::Value>(data).is_ok());
+    }
+
+    let canonical: &[&[u8]] = &[
+        b"i0e",
+        b"i-3e",
+        b"i30e",
+        b"0:",
+        b"3:abc",
+        b"d1:ai1e2:aai2e1:bi3ee",
+    ];
+    for data in canonical {
+        assert!(from_bytes_with_options::<crate::Value>(data, strict).is_ok());
+    }
+
+    // never valid, whatever the mode
+    for data in &[&b"i+3e"[..], b"i+0e", b"i-e", b"ie", b"i--3e"] {
+        assert!(from_bytes_with_options::<crate::Value>(data, strict).is_err());
+        assert!(from_bytes::<crate::Value>(data).is_err());
+        assert!(from_bytes::<i64>(data).is_err());
+        assert!(from_bytes::<u64>(data).is_err());
+    }
+}
+
+#[test]
+fn test_char() {
+    assert_eq!(from_bytes::<char>(b"1:x").unwrap(), 'x');
+    assert_eq!(from_bytes::<char>("2:\u{e9}".as_bytes()).unwrap(), '\u{e9}');
+    assert!(from_bytes::<char>(b"2:xy").is_err());
+    assert!(from_bytes::<char>(b"0:").is_err());
+    assert_eq!(
+        from_bytes::<f64>(b"i1e").unwrap_err().kind(),
+        &ErrorKind::UnsupportedType { type_name: "f64" }
+    );
+}
+
+#[test]
+fn test_bool() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        private: bool,
+        seed: bool,
+    }
+    let x = X {
+        private: true,
+        seed: false,
+    };
+    assert_eq!(from_bytes::<X>(b"d7:privatei1e4:seedi0ee").unwrap(), x);
+    assert_eq!(from_bytes::<X>(b"d7:privatei2e4:seed5:falsee").unwrap(), x);
+
+    let strict = DecodeOptions {
+        strict: true,
+        ..DecodeOptions::default()
+    };
+    assert!(from_bytes_with_options::<X>(b"d7:privatei1e4:seedi0ee", strict).is_ok());
+    assert!(from_bytes_with_options::<X>(b"d7:privatei2e4:seedi0ee", strict).is_err());
+    assert!(from_bytes_with_options::<X>(b"d7:privatei1e4:seed5:falsee", strict).is_err());
+
+    let strings = DecodeOptions {
+        strict: true,
+        bool_repr: BoolRepr::String,
+    };
+    let data = b"d7:private4:true4:seed5:falsee";
+    assert_eq!(from_bytes_with_options::<X>(data, strings).unwrap(), x);
+    assert!(from_bytes_with_options::<X>(b"d7:privatei1e4:seedi0ee", strings).is_err());
+
+    let reject = DecodeOptions {
+        bool_repr: BoolRepr::Reject,
+        ..DecodeOptions::default()
+    };
+    assert!(from_bytes_with_options::<X>(b"d7:privatei1e4:seedi0ee", reject).is_err());
+}
+
+#[test]
+fn test_reader() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        name: String,
+        #[serde(with = "serde_bytes")]
+        pieces: Vec<u8>,
+        files: Vec<crate::Value>,
+    }
+    let data = b"d5:filesli-1ed1:ai1eee4:name4:test6:pieces3:\x00\x01\x02e";
+
+    let x = from_reader::<_, X>(&data[..]).unwrap();
+    let y = X {
+        name: "test".to_string(),
+        pieces: vec![0, 1, 2],
+        files: vec![
+            crate::Value::Int(-1),
+            crate::decode::from_bytes(b"d1:ai1ee").unwrap(),
+        ],
+    };
+
+    assert_eq!(x, y);
+    assert!(from_reader::<_, X>(&data[..data.len() - 1]).is_err());
+    assert!(from_reader::<_, crate::Value>(&b"i1ei2e"[..]).is_err());
+
+    // digits that never end are cut off once no integer could hold them
+    use std::io::Read as _;
+    let endless = |prefix: &'static [u8]| prefix.chain(std::io::repeat(b'9'));
+    let err = from_reader::<_, crate::Value>(endless(b"i")).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidInteger);
+    assert_eq!(err.offset(), Some(22));
+    let err = from_reader::<_, crate::Value>(endless(b"li1e")).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidLength);
+    assert_eq!(err.offset(), Some(24));
+    assert_eq!(
+        from_reader::<_, u64>(&b"i18446744073709551615e"[..]).unwrap(),
+        u64::MAX
+    );
+    assert_eq!(
+        from_reader::<_, i64>(&b"i-9223372036854775808e"[..]).unwrap(),
+        i64::MIN
+    );
+}
+
+#[test]
+fn test_error_location() {
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct File {
+        length: u64,
+    }
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Info {
+        files: Vec<File>,
+    }
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct Torrent {
+        info: Info,
+    }
+
+    let data = b"d4:infod5:filesld6:lengthi1eed6:lengthi-1eeeee";
+    let err = from_bytes::<Torrent>(data).unwrap_err();
+    assert_eq!(err.offset(), Some(38));
+    assert_eq!(err.path_string(), "info.files[1].length");
+
+    let err = from_bytes::<Torrent>(b"d4:infod5:filesld6:lengthi1x").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedEof);
+    assert_eq!(err.path_string(), "info.files[0].length");
+
+    let err = from_bytes::<crate::Value>(b"li1eq").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedToken);
+    assert_eq!(err.offset(), Some(4));
+    assert_eq!(err.expected_token(), Some("value"));
+    assert_eq!(err.found_token(), Some("'q'"));
+
+    // `Value` turns down integers past `i64::MAX`, at the integer
+    let big = b"i1ei18446744073709551615e";
+    let err = from_bytes::<crate::Value>(b"d1:ali1ei18446744073709551615eee").unwrap_err();
+    assert_eq!(err.offset(), Some(8));
+    assert_eq!(err.path_string(), "a[1]");
+    let mut deserializer = Deserializer::from_bytes(big);
+    crate::Value::deserialize(&mut deserializer).unwrap();
+    let err = crate::Value::deserialize(&mut deserializer).unwrap_err();
+    assert_eq!(err.offset(), Some(3));
+    let mut deserializer = Deserializer::from_reader(&big[..]);
+    crate::Value::deserialize(&mut deserializer).unwrap();
+    let err = crate::Value::deserialize(&mut deserializer).unwrap_err();
+    assert_eq!(err.offset(), Some(3));
+
+    let err = from_bytes::<crate::Value>(b"i1ei2e").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::TrailingBytes);
+    assert_eq!(err.offset(), Some(3));
+
+    let strict = DecodeOptions {
+        strict: true,
+        ..DecodeOptions::default()
+    };
+    let err = from_bytes_with_options::<crate::Value>(b"d1:bi1e1:ai2ee", strict).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnsortedKeys);
+    assert_eq!(err.offset(), Some(7));
+}
+
+#[test]
+fn test_truncated() {
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    enum E {
+        A(u64),
+        B { x: String },
+    }
+    #[derive(serde::Deserialize, Debug)]
+    #[allow(dead_code)]
+    struct X<'a> {
+        a: Vec<i64>,
+        b: &'a [u8],
+        c: E,
+        d: crate::Value,
+    }
+
+    let data = b"d1:ali1ei-2ee1:b3:xyz1:cd1:Bd1:x2:hiee1:dd1:kl0:eee";
+    assert!(from_bytes::<X>(data).is_ok());
+
+    for end in 0..data.len() {
+        let input = &data[..end];
+        let err = from_bytes::<X>(input).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnexpectedEof, "{:?}", input);
+        let err = from_bytes::<crate::Value>(input).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnexpectedEof, "{:?}", input);
+        let err = from_reader::<_, crate::Value>(input).unwrap_err();
+        assert_eq!(err.kind(), &ErrorKind::UnexpectedEof, "{:?}", input);
+    }
+
+    let malformed: &[&[u8]] = &[
+        b"l",
+        b"d3:foo",
+        b"d",
+        b"i",
+        b"ie",
+        b"i-e",
+        b"3",
+        b"3:",
+        b"99999999999999999999:",
+        b"d1:ae",
+        b"di1ei1ee",
+        b"x",
+        b"l1:a",
+        b"d1:Cd",
+        b"d1:B3:\xff\xfe\x00",
+    ];
+    for input in malformed {
+        assert!(from_bytes::<crate::Value>(input).is_err());
+        assert!(from_bytes::<crate::ValueRef>(input).is_err());
+        assert!(from_bytes::<E>(input).is_err());
+        assert!(from_reader::<_, crate::Value>(&input[..]).is_err());
+    }
+
+    let err = from_bytes::<E>(b"2:\xff\xfe").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidUtf8);
+
+    let deep = vec![b'l'; 100_000];
+    let err = from_bytes::<crate::Value>(&deep).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::DepthLimitExceeded);
+}
+
+#[test]
+fn test_option() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        comment: Option<String>,
+        length: Option<i64>,
+    }
+    let x = X {
+        comment: None,
+        length: Some(7),
+    };
+    assert_eq!(from_bytes::<X>(b"d6:lengthi7ee").unwrap(), x);
+    assert_eq!(
+        from_bytes::<X>(b"de").unwrap(),
+        X {
+            comment: None,
+            length: None
+        }
+    );
+}
+
+#[test]
+fn test_owned() {
+    use serde::de::DeserializeOwned;
+    use serde_bytes::ByteBuf;
+    use std::borrow::Cow;
+
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct Owned {
+        name: String,
+        pieces: ByteBuf,
+    }
+
+    // the input is dropped before the value, so nothing may borrow from it
+    fn load<T: DeserializeOwned>(data: Vec<u8>) -> T {
+        from_bytes(&data).unwrap()
+    }
+    let x: Owned = load(b"d4:name3:foo6:pieces3:\x00\x01\x02e".to_vec());
+    assert_eq!(x.name, "foo");
+    assert_eq!(x.pieces.as_ref(), b"\x00\x01\x02");
+
+    #[derive(serde::Deserialize)]
+    struct Borrowed<'a> {
+        #[serde(borrow)]
+        name: Cow<'a, str>,
+        #[serde(borrow, with = "serde_bytes")]
+        pieces: Cow<'a, [u8]>,
+    }
+    let data = b"d4:name3:foo6:pieces3:\x00\x01\x02e";
+    let x: Borrowed = from_bytes(data).unwrap();
+    assert!(matches!(x.name, Cow::Borrowed("foo")));
+    assert!(matches!(x.pieces, Cow::Borrowed(b"\x00\x01\x02")));
+
+    // a reader can only hand out copies
+    let mut de = Deserializer::from_reader(&data[..]);
+    let x = Borrowed::deserialize(&mut de).unwrap();
+    assert!(matches!(x.name, Cow::Owned(_)));
+    assert_eq!(x.pieces.as_ref(), b"\x00\x01\x02");
+}
+
+#[test]
+fn test_byte_array() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct X {
+        #[serde(with = "crate::bytes_array")]
+        id: [u8; 4],
+        array: serde_bytes::ByteArray<3>,
+        pair: (i64, String),
+    }
+    let x: X = from_bytes(b"d5:array3:abc2:id4:\x00\x01\x02\x034:pairli1e1:xee").unwrap();
+    assert_eq!(x.id, [0, 1, 2, 3]);
+    assert_eq!(*x.array, *b"abc");
+    assert_eq!(x.pair, (1, "x".to_string()));
+
+    // plain arrays and tuples are lists, a byte string doesn't stand in
+    assert_eq!(from_bytes::<[u8; 2]>(b"li1ei2ee").unwrap(), [1, 2]);
+    assert!(from_bytes::<[u8; 2]>(b"2:ab").is_err());
+    assert!(from_bytes::<(u8, u8)>(b"2:ab").is_err());
+
+    let err = from_bytes::<X>(b"d5:array3:abc2:id5:abcdee").unwrap_err();
+    assert_eq!(err.path_string(), "id");
+    assert_eq!(err.offset(), Some(17));
+}
+
+#[test]
+fn test_enum() {
+    use crate::encode::to_bytes;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    enum E {
+        Unit,
+        Newtype(String),
+        Tuple(u8, i64),
+        Struct { b: u8, a: Vec<E> },
+    }
+
+    let cases = vec![
+        (E::Unit, b"4:Unit".to_vec()),
+        (E::Newtype("x".to_string()), b"d7:Newtype1:xe".to_vec()),
+        (E::Tuple(1, -2), b"d5:Tupleli1ei-2eee".to_vec()),
+        (
+            E::Struct {
+                b: 1,
+                a: vec![E::Unit, E::Tuple(0, 0)],
+            },
+            b"d6:Structd1:al4:Unitd5:Tupleli0ei0eeee1:bi1eee".to_vec(),
+        ),
+    ];
+    for (value, encoded) in cases {
+        assert_eq!(to_bytes(&value).unwrap(), encoded);
+        assert_eq!(from_bytes::<E>(&encoded).unwrap(), value);
+        assert_eq!(from_reader::<_, E>(&encoded[..]).unwrap(), value);
+    }
+
+    assert!(from_bytes::<E>(b"d4:Unit0:e").is_err());
+    assert!(from_bytes::<E>(b"7:Newtype").is_err());
+    assert!(from_bytes::<E>(b"5:Other").is_err());
+    // the dict form holds exactly one variant
+    let err = from_bytes::<E>(b"d7:Newtype1:x4:Unit0:e").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedToken);
+}
+
+#[test]
+fn test_tagged() {
+    use crate::encode::to_bytes;
+    use std::collections::BTreeMap;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    struct Args {
+        id: crate::NodeId,
+        #[serde(default, deserialize_with = "option_bool_from_any")]
+        implied_port: Option<bool>,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(tag = "y")]
+    enum Krpc {
+        #[serde(rename = "q")]
+        Query {
+            #[serde(with = "serde_bytes")]
+            t: Vec<u8>,
+            q: String,
+            a: Args,
+        },
+        #[serde(rename = "r")]
+        Response {
+            #[serde(with = "serde_bytes")]
+            t: Vec<u8>,
+            r: Values,
+        },
+        #[serde(rename = "e")]
+        Error { t: String, e: (i64, String) },
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    struct Values {
+        id: crate::NodeId,
+        token: crate::InfoHashV1,
+    }
+
+    let query = Krpc::Query {
+        t: b"aa".to_vec(),
+        q: "ping".to_string(),
+        a: Args {
+            id: crate::NodeId(*b"abcdefghij0123456789"),
+            implied_port: Some(true),
+        },
+    };
+    let data = b"d1:ad2:id20:abcdefghij012345678912:implied_porti1ee1:q4:ping1:t2:aa1:y1:qe";
+    assert_eq!(to_bytes(&query).unwrap(), data.to_vec());
+    assert_eq!(from_bytes::<Krpc>(data).unwrap(), query);
+    assert_eq!(from_reader::<_, Krpc>(&data[..]).unwrap(), query);
+
+    // ids that aren't valid utf-8 come through serde's buffer as bytes
+    let response = Krpc::Response {
+        t: b"aa".to_vec(),
+        r: Values {
+            id: crate::NodeId([0xff; 20]),
+            token: crate::InfoHashV1([0x80; 20]),
+        },
+    };
+    let data = to_bytes(&response).unwrap();
+    assert_eq!(&data[..9], b"d1:rd2:id");
+    assert_eq!(from_bytes::<Krpc>(&data).unwrap(), response);
+    assert_eq!(from_reader::<_, Krpc>(&data[..]).unwrap(), response);
+    let mut short = b"d1:rd2:id19:".to_vec();
+    short.extend_from_slice(&[0xff; 19]);
+    short.extend_from_slice(b"5:token0:e1:t2:aa1:y1:re");
+    assert!(from_bytes::<Krpc>(&short).is_err());
+
+    let error = Krpc::Error {
+        t: "aa".to_string(),
+        e: (201, "A Generic Error Ocurred".to_string()),
+    };
+    let data = b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
+    assert_eq!(to_bytes(&error).unwrap(), data.to_vec());
+    assert_eq!(from_bytes::<Krpc>(data).unwrap(), error);
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(tag = "t", content = "c")]
+    enum Adjacent {
+        Ping(i64),
+        Pong {
+            seq: i64,
+            #[serde(deserialize_with = "bool_from_any")]
+            ok: bool,
+        },
+    }
+    let pong = Adjacent::Pong { seq: 3, ok: true };
+    let data = b"d1:cd2:oki1e3:seqi3ee1:t4:Ponge";
+    assert_eq!(to_bytes(&pong).unwrap(), data.to_vec());
+    assert_eq!(from_bytes::<Adjacent>(data).unwrap(), pong);
+    assert_eq!(
+        from_bytes::<Adjacent>(b"d1:ci7e1:t4:Pinge").unwrap(),
+        Adjacent::Ping(7)
+    );
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Eq)]
+    struct Flat {
+        name: String,
+        #[serde(deserialize_with = "bool_from_any")]
+        private: bool,
+        #[serde(flatten)]
+        rest: BTreeMap<String, crate::Value>,
+    }
+    let data = b"d5:extrai1e4:name1:x7:privatei0e5:zzzzzl0:ee";
+    let flat = from_bytes::<Flat>(data).unwrap();
+    assert_eq!(flat.name, "x");
+    assert!(!flat.private);
+    assert_eq!(flat.rest.len(), 2);
+    assert_eq!(flat.rest["extra"], crate::Value::Int(1));
+    assert_eq!(to_bytes(&flat).unwrap(), data.to_vec());
+}
+
+#[test]
+fn test_prefix() {
+    #[derive(serde::Deserialize, Debug, PartialEq, Eq)]
+    struct Metadata {
+        msg_type: u8,
+        piece: u32,
+        total_size: u64,
+    }
+    let data = b"d8:msg_typei1e5:piecei0e10:total_sizei34256eexxxxxxxx";
+    let (header, rest) = from_bytes_prefix::<Metadata>(data).unwrap();
+    assert_eq!(
+        header,
+        Metadata {
+            msg_type: 1,
+            piece: 0,
+            total_size: 34256
+        }
+    );
+    assert_eq!(rest, b"xxxxxxxx");
+    assert!(from_bytes::<Metadata>(data).is_err());
+
+    let (value, rest) = from_bytes_prefix::<i64>(b"i3e").unwrap();
+    assert_eq!((value, rest), (3, &b""[..]));
+    let err = from_bytes_prefix::<Metadata>(b"d8:msg_typei1e").unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_stream() {
+    let data = b"i1ed1:ai2ee3:abci-4e";
+
+    let mut stream = Deserializer::from_bytes(data).into_stream::<crate::Value>();
+    let mut offsets = vec![stream.byte_offset()];
+    let mut values = Vec::new();
+    while let Some(value) = stream.next() {
+        values.push(value.unwrap());
+        offsets.push(stream.byte_offset());
+    }
+    assert_eq!(offsets, [0, 3, 11, 16, 20]);
+    assert_eq!(values[0], crate::Value::Int(1));
+    assert_eq!(values[2], crate::Value::from("abc"));
+
+    let stream = Deserializer::from_reader(&data[..]).into_stream::<crate::Value>();
+    let from_reader: Vec<_> = stream.map(Result::unwrap).collect();
+    assert_eq!(from_reader, values);
+
+    // an error ends the stream
+    let mut stream =
+        StreamDeserializer::<_, i64>::new(SliceRead::new(b"i1ei2xi3e"), DecodeOptions::default());
+    assert_eq!(stream.next().unwrap().unwrap(), 1);
+    let err = stream.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::InvalidInteger);
+    assert_eq!(err.offset(), Some(3));
+    assert!(stream.next().is_none());
+    assert!(Deserializer::from_bytes(b"")
+        .into_stream::<i64>()
+        .next()
+        .is_none());
+}
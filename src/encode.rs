@@ -1,23 +1,160 @@
-use serde::{ser::{self}, Serialize};
+use std::io::Write;
 
-use crate::Error;
+use serde::{
+    ser::{self},
+    Serialize,
+};
+
+use crate::{raw, BoolRepr, Error, ErrorKind};
 
 // functionality for encoding bencode trees
 
-pub struct Serializer {
-    output: Vec<u8>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// emit dict keys sorted by their raw bytes as BEP 3 requires, turning
+    /// this off keeps the order serde hands them over, which can help debugging
+    pub sort_keys: bool,
+    pub bool_repr: BoolRepr,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            sort_keys: true,
+            bool_repr: BoolRepr::default(),
+        }
+    }
+}
+
+pub struct Serializer<W> {
+    output: W,
+    options: EncodeOptions,
+    written: usize,
+    /// encoded key of the dict entry being written, held back until its value
+    /// writes something so that `None` entries can be left out
+    key: Option<Vec<u8>>,
+    /// the next dict opened is known to have its keys in canonical order
+    sorted: bool,
 }
 
+impl<W: Write> Serializer<W> {
+    pub fn new(output: W) -> Self {
+        Self::with_options(output, EncodeOptions::default())
+    }
+
+    pub fn with_options(output: W, options: EncodeOptions) -> Self {
+        Serializer {
+            output,
+            options,
+            written: 0,
+            key: None,
+            sorted: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if let Some(key) = self.key.take() {
+            self.output.write_all(&key).map_err(Error::io)?;
+            self.written += key.len();
+        }
+        self.output.write_all(bytes).map_err(Error::io)?;
+        self.written += bytes.len();
+        Ok(())
+    }
+
+    /// encodes a value that has to produce output, `None` writes nothing and
+    /// only has a representation as a dict entry that is left out
+    fn present<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let before = self.written;
+        self.encode(value)?;
+        if self.written == before {
+            return Err(Error::new(ErrorKind::UnexpectedNone));
+        }
+        Ok(())
+    }
+
+    /// encodes a value, first checking whether a dict it opens already has
+    /// its keys in canonical order and can be written straight through
+    fn encode<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.sorted = self.options.sort_keys && is_sorted_dict(value, self.options);
+        value.serialize(&mut *self)
+    }
+
+    /// encodes a single value into a fresh buffer with the same options
+    fn buffer<T>(&self, value: &T) -> Result<Vec<u8>, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        buffer(value, self.options)
+    }
+}
+
+fn buffer<T>(value: &T, options: EncodeOptions) -> Result<Vec<u8>, Error>
+where
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::with_options(Vec::new(), options);
+    serializer.encode(value)?;
+    Ok(serializer.output)
+}
+
+/// encodes a value into a fresh buffer. serde hands fixed size arrays over
+/// the same way as tuples, so a `[u8; N]` becomes a list of ints. mark the
+/// field `#[serde(with = "bencode::bytes_array")]`, or use the id types like
+/// `InfoHashV1`, to get a byte string that decodes back to the same array
 pub fn to_bytes<T>(value: &T) -> Result<Vec<u8>, Error>
 where
-    T: Serialize,
+    T: ?Sized + Serialize,
+{
+    to_bytes_with_options(value, EncodeOptions::default())
+}
+
+pub fn to_bytes_with_options<T>(value: &T, options: EncodeOptions) -> Result<Vec<u8>, Error>
+where
+    T: ?Sized + Serialize,
 {
-    let mut serializer = Serializer { output: Vec::new() };
-    value.serialize(&mut serializer)?;
+    let mut serializer = Serializer::with_options(Vec::new(), options);
+    serializer.present(value)?;
     Ok(serializer.output)
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+/// streams the encoding straight into `writer`. with `sort_keys` on, a dict
+/// whose keys don't already come out in canonical order is encoded in memory
+/// first so they can be sorted. that holds its entries and whatever they
+/// contain, but a `BTreeMap` or a struct with its fields declared in sorted
+/// order goes straight through
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<(), Error>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    to_writer_with_options(writer, value, EncodeOptions::default())
+}
+
+pub fn to_writer_with_options<W, T>(
+    writer: W,
+    value: &T,
+    options: EncodeOptions,
+) -> Result<(), Error>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    let mut serializer = Serializer::with_options(writer, options);
+    serializer.present(value)
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
     type Ok = ();
 
     type Error = Error;
@@ -26,50 +163,70 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
     type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    type SerializeMap = MapSerializer<'a, W>;
+    type SerializeStruct = MapSerializer<'a, W>;
+    type SerializeStructVariant = MapSerializer<'a, W>;
 
-    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
+        match self.options.bool_repr {
+            BoolRepr::Int => self.serialize_u64(u64::from(v)),
+            BoolRepr::String if v => self.serialize_str("true"),
+            BoolRepr::String => self.serialize_str("false"),
+            BoolRepr::Reject => Err(Error::unsupported("bool")),
+        }
     }
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
     }
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
     }
-    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_i64(i64::from(v))
     }
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
+        self.write(b"i")?;
+        self.write(v.to_string().as_bytes())?;
+        self.write(b"e")
     }
-    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+
+    fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
+        self.write(b"i")?;
+        self.write(v.to_string().as_bytes())?;
+        self.write(b"e")
     }
-    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
+        self.serialize_u64(u64::from(v))
     }
-    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
+        self.serialize_u64(u64::from(v))
+    }
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
+        self.serialize_u64(u64::from(v))
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.output.push(b'i');
-        self.output.extend_from_slice(v.to_string().as_bytes());
-        self.output.push(b'e');
-        Ok(())
+        self.write(b"i")?;
+        self.write(v.to_string().as_bytes())?;
+        self.write(b"e")
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
+        self.write(b"i")?;
+        self.write(v.to_string().as_bytes())?;
+        self.write(b"e")
     }
 
     fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+        Err(Error::unsupported("f32"))
     }
     fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+        Err(Error::unsupported("f64"))
     }
-    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
@@ -77,49 +234,53 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.output
-            .extend_from_slice(v.len().to_string().as_bytes());
-        self.output.push(b':');
-        self.output.extend_from_slice(v);
-        Ok(())
+        self.write(v.len().to_string().as_bytes())?;
+        self.write(b":")?;
+        self.write(v)
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
         Ok(())
     }
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok, Self::Error>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
         value.serialize(self)
     }
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+        Err(Error::unsupported("()"))
     }
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Err(Error::unsupported(name))
     }
     fn serialize_unit_variant(
         self,
         _name: &'static str,
         _variant_index: u32,
-        _variant: &'static str,
+        variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        unimplemented!()
+        self.serialize_str(variant)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
+    fn serialize_newtype_struct<T>(
         self,
-        _name: &'static str,
+        name: &'static str,
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
+        if name == raw::TOKEN {
+            // a `RawValue` hands over its bytes as a byte string, unwrap it
+            // and write them out verbatim
+            let raw = key_bytes(self.buffer(value)?)?;
+            return self.write(&raw);
+        }
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
         _name: &'static str,
         _variant_index: u32,
@@ -127,17 +288,16 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         value: &T,
     ) -> Result<Self::Ok, Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        self.output.extend_from_slice(b"d");
+        self.write(b"d")?;
         variant.serialize(&mut *self)?;
-        self.output.extend_from_slice(b"e");
-        value.serialize(&mut *self)?;
-        Ok(())
+        self.present(value)?;
+        self.write(b"e")
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        self.output.extend_from_slice(b"l");
+        self.write(b"l")?;
         Ok(self)
     }
 
@@ -160,15 +320,14 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.output.extend_from_slice(b"d");
+        self.write(b"d")?;
         variant.serialize(&mut *self)?;
-        self.output.extend_from_slice(b"l");
+        self.write(b"l")?;
         Ok(self)
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        self.output.extend_from_slice(b"d");
-        Ok(self)
+        MapSerializer::new(self, false)
     }
 
     fn serialize_struct(
@@ -186,150 +345,537 @@ impl<'a> ser::Serializer for &'a mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.output.extend_from_slice(b"d");
+        self.write(b"d")?;
         variant.serialize(&mut *self)?;
-        self.output.extend_from_slice(b"d");
-        Ok(self)
+        MapSerializer::new(self, true)
     }
 }
 
-impl<'a> ser::SerializeSeq for &'a mut Serializer {
+impl<W: Write> ser::SerializeSeq for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        self.present(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"e");
-        Ok(())
+        self.write(b"e")
     }
 }
 
-impl<'a> ser::SerializeTuple for &'a mut Serializer {
+impl<W: Write> ser::SerializeTuple for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        self.present(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"e");
-        Ok(())
+        self.write(b"e")
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for &'a mut Serializer {
+impl<W: Write> ser::SerializeTupleStruct for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        self.present(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"e");
-        Ok(())
+        self.write(b"e")
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for &'a mut Serializer {
+impl<W: Write> ser::SerializeTupleVariant for &mut Serializer<W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        self.present(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"ee");
+        self.write(b"ee")
+    }
+}
+
+/// writes dict entries straight through, or buffers them when they have to
+/// be sorted into canonical key order first
+pub struct MapSerializer<'a, W> {
+    ser: &'a mut Serializer<W>,
+    /// the encoded entries of a dict that isn't in canonical order yet, `None`
+    /// while they go straight to the output
+    entries: Option<Vec<(Vec<u8>, Vec<u8>)>>,
+    /// the last key written straight through
+    last: Option<Vec<u8>>,
+    key: Option<Vec<u8>>,
+    variant: bool,
+}
+
+impl<'a, W: Write> MapSerializer<'a, W> {
+    fn new(ser: &'a mut Serializer<W>, variant: bool) -> Result<Self, Error> {
+        let stream = !ser.options.sort_keys || std::mem::take(&mut ser.sorted);
+        if stream {
+            ser.write(b"d")?;
+        }
+        Ok(MapSerializer {
+            ser,
+            entries: if stream { None } else { Some(Vec::new()) },
+            last: None,
+            key: None,
+            variant,
+        })
+    }
+
+    fn push<T>(&mut self, key: Vec<u8>, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Some(entries) = &mut self.entries {
+            let value = self.ser.buffer(value)?;
+            // an empty encoding can only come from `None`, so leave the key out
+            if !value.is_empty() {
+                entries.push((key, value));
+            }
+            return Ok(());
+        }
+
+        let mut prefix = key.len().to_string().into_bytes();
+        prefix.push(b':');
+        prefix.extend_from_slice(&key);
+        self.ser.key = Some(prefix);
+        self.ser.encode(value)?;
+        // the key is still held back if the value was `None`
+        if self.ser.key.take().is_some() || !self.ser.options.sort_keys {
+            return Ok(());
+        }
+        if self.last.as_ref().is_some_and(|last| *last >= key) {
+            return Err(Error::message(
+                "dict keys came out in a different order than when they were checked",
+            ));
+        }
+        self.last = Some(key);
         Ok(())
     }
+
+    fn finish(self) -> Result<(), Error> {
+        let end: &[u8] = if self.variant { b"ee" } else { b"e" };
+        let mut entries = match self.entries {
+            Some(entries) => entries,
+            None => return self.ser.write(end),
+        };
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        if entries.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(Error::new(ErrorKind::DuplicateKey));
+        }
+
+        self.ser.write(b"d")?;
+        for (key, value) in &entries {
+            self.ser.write(key.len().to_string().as_bytes())?;
+            self.ser.write(b":")?;
+            self.ser.write(key)?;
+            self.ser.write(value)?;
+        }
+        self.ser.write(end)
+    }
+}
+
+/// recovers the raw bytes of a key, which must have encoded to a byte string
+fn key_bytes(encoded: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match encoded.iter().position(|&b| b == b':') {
+        Some(colon) if encoded[0].is_ascii_digit() => Ok(encoded[colon + 1..].to_vec()),
+        _ => Err(Error::new(ErrorKind::InvalidKey)),
+    }
 }
 
-impl<'a> ser::SerializeMap for &'a mut Serializer {
+impl<W: Write> ser::SerializeMap for MapSerializer<'_, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<(), Self::Error>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)
+        self.key = Some(key_bytes(self.ser.buffer(key)?)?);
+        Ok(())
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<(), Self::Error>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        value.serialize(&mut **self)
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| Error::message("dict value without a key"))?;
+        self.push(key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"e");
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a> ser::SerializeStruct for &'a mut Serializer {
+impl<W: Write> ser::SerializeStruct for MapSerializer<'_, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)
+        self.push(key.as_bytes().to_vec(), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"e");
-        Ok(())
+        self.finish()
     }
 }
 
-impl<'a> ser::SerializeStructVariant for &'a mut Serializer {
+impl<W: Write> ser::SerializeStructVariant for MapSerializer<'_, W> {
     type Ok = ();
     type Error = Error;
 
-    fn serialize_field<T: ?Sized>(
-        &mut self,
-        key: &'static str,
-        value: &T,
-    ) -> Result<(), Self::Error>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Self::Error>
     where
-        T: Serialize,
+        T: ?Sized + Serialize,
     {
-        key.serialize(&mut **self)?;
-        value.serialize(&mut **self)
+        self.push(key.as_bytes().to_vec(), value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output.extend_from_slice(b"ee");
+        self.finish()
+    }
+}
+
+/// what `Probe` found out about a value
+#[derive(PartialEq, Eq)]
+enum Shape {
+    /// `None`, which leaves a dict entry out
+    Absent,
+    /// a dict whose keys, leaving out `None` entries, come out sorted and unique
+    Sorted,
+    Other,
+}
+
+fn is_sorted_dict<T>(value: &T, options: EncodeOptions) -> bool
+where
+    T: ?Sized + Serialize,
+{
+    let probe = Probe {
+        options,
+        keys: true,
+    };
+    matches!(value.serialize(probe), Ok(Shape::Sorted))
+}
+
+/// walks a value without encoding it, to find out ahead of time whether a
+/// dict can be written straight through. only a dict's keys are looked at,
+/// its values are only checked for `None`, and with `keys` off not even that
+struct Probe {
+    options: EncodeOptions,
+    keys: bool,
+}
+
+impl ser::Serializer for Probe {
+    type Ok = Shape;
+    type Error = Error;
+
+    type SerializeSeq = Skip;
+    type SerializeTuple = Skip;
+    type SerializeTupleStruct = Skip;
+    type SerializeTupleVariant = Skip;
+    type SerializeMap = KeyProbe;
+    type SerializeStruct = KeyProbe;
+    type SerializeStructVariant = KeyProbe;
+
+    fn serialize_bool(self, _v: bool) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i8(self, _v: i8) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i16(self, _v: i16) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i32(self, _v: i32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i64(self, _v: i64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_i128(self, _v: i128) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u8(self, _v: u8) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u16(self, _v: u16) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u32(self, _v: u32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u64(self, _v: u64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_u128(self, _v: u128) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_f32(self, _v: f32) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_f64(self, _v: f64) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_char(self, _v: char) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_str(self, _v: &str) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_none(self) -> Result<Shape, Error> {
+        Ok(Shape::Absent)
+    }
+    fn serialize_some<T>(self, value: &T) -> Result<Shape, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Shape, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if name == raw::TOKEN {
+            return Ok(Shape::Other);
+        }
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Shape, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Shape::Other)
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Skip, Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Skip, Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Skip, Error> {
+        Ok(Skip)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Skip, Error> {
+        Ok(Skip)
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<KeyProbe, Error> {
+        Ok(KeyProbe {
+            options: self.options,
+            sorted: self.keys,
+            last: None,
+            key: None,
+        })
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<KeyProbe, Error> {
+        self.serialize_map(Some(len))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        len: usize,
+    ) -> Result<KeyProbe, Error> {
+        self.serialize_map(Some(len))
+    }
+}
+
+/// passes over the elements of anything that isn't a dict
+struct Skip;
+
+impl ser::SerializeSeq for Skip {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl ser::SerializeTuple for Skip {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl ser::SerializeTupleStruct for Skip {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
+        Ok(())
+    }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
+}
+
+impl ser::SerializeTupleVariant for Skip {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, _value: &T) -> Result<(), Error> {
         Ok(())
     }
+    fn end(self) -> Result<Shape, Error> {
+        Ok(Shape::Other)
+    }
 }
 
+/// checks that a dict's keys arrive in canonical order
+struct KeyProbe {
+    options: EncodeOptions,
+    /// cleared as soon as a key is out of order, after which the rest of the
+    /// entries are passed over
+    sorted: bool,
+    last: Option<Vec<u8>>,
+    key: Option<Vec<u8>>,
+}
 
+impl KeyProbe {
+    fn entry<T>(&mut self, key: Vec<u8>, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let probe = Probe {
+            options: self.options,
+            keys: false,
+        };
+        if !self.sorted || value.serialize(probe)? == Shape::Absent {
+            return Ok(());
+        }
+        if self.last.as_ref().is_some_and(|last| *last >= key) {
+            self.sorted = false;
+        }
+        self.last = Some(key);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Shape, Error> {
+        Ok(if self.sorted {
+            Shape::Sorted
+        } else {
+            Shape::Other
+        })
+    }
+}
+
+impl ser::SerializeMap for KeyProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), Error> {
+        if self.sorted {
+            self.key = Some(key_bytes(buffer(key, self.options)?)?);
+        }
+        Ok(())
+    }
+    fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match self.key.take() {
+            Some(key) => self.entry(key, value),
+            None => Ok(()),
+        }
+    }
+    fn end(self) -> Result<Shape, Error> {
+        KeyProbe::end(self)
+    }
+}
+
+impl ser::SerializeStruct for KeyProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.entry(key.as_bytes().to_vec(), value)
+    }
+    fn end(self) -> Result<Shape, Error> {
+        KeyProbe::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for KeyProbe {
+    type Ok = Shape;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.entry(key.as_bytes().to_vec(), value)
+    }
+    fn end(self) -> Result<Shape, Error> {
+        KeyProbe::end(self)
+    }
+}
 
 #[test]
 fn test1() {
@@ -344,7 +890,7 @@ fn test1() {
         b: C,
     }
 
-    let data = b"d3:numi1e1:bd1:ii32e1:ji64eee";
+    let data = b"d1:bd1:ii32e1:ji64ee3:numi1ee";
     let y = X {
         num: 1,
         b: C { i: 32, j: 64 },
@@ -370,7 +916,7 @@ fn test2() {
         b: C<'a>,
     }
 
-    let data = b"d3:numi1e1:bd1:ii32e1:j4:weedee";
+    let data = b"d1:bd1:ii32e1:j4:weede3:numi1ee";
     let y = X {
         num: 1,
         b: C { i: 32, j: b"weed" },
@@ -433,3 +979,284 @@ fn test3() {
     let x = to_bytes::<X>(&y).unwrap();
     assert_eq!(x, data)
 }
+
+#[test]
+fn test_signed() {
+    #[derive(serde::Serialize)]
+    struct X {
+        a: i8,
+        b: i16,
+        c: i32,
+        d: i64,
+        e: i128,
+        f: u8,
+        g: u16,
+        h: u32,
+        i: u128,
+    }
+
+    let data = b"d1:ai-128e1:bi-3e1:ci0e1:di-9223372036854775808e1:ei-170141183460469231731687303715884105728e1:fi255e1:gi65535e1:hi4294967295e1:ii340282366920938463463374607431768211455ee";
+    let y = X {
+        a: i8::MIN,
+        b: -3,
+        c: 0,
+        d: i64::MIN,
+        e: i128::MIN,
+        f: u8::MAX,
+        g: u16::MAX,
+        h: u32::MAX,
+        i: u128::MAX,
+    };
+
+    let x = to_bytes::<X>(&y).unwrap();
+    assert_eq!(x, &data[..])
+}
+
+#[test]
+fn test_sorted_keys() {
+    use std::collections::HashMap;
+
+    #[derive(serde::Serialize)]
+    struct Inner {
+        zz: u64,
+        aa: u64,
+    }
+    #[derive(serde::Serialize)]
+    struct X {
+        y: u64,
+        #[serde(flatten)]
+        inner: Inner,
+        map: HashMap<&'static str, u64>,
+    }
+
+    let mut map = HashMap::new();
+    map.insert("b", 2);
+    map.insert("a", 1);
+    map.insert("ab", 3);
+    let y = X {
+        y: 1,
+        inner: Inner { zz: 2, aa: 3 },
+        map,
+    };
+
+    let x = to_bytes(&y).unwrap();
+    assert_eq!(x, &b"d2:aai3e3:mapd1:ai1e2:abi3e1:bi2ee1:yi1e2:zzi2ee"[..]);
+
+    let options = EncodeOptions {
+        sort_keys: false,
+        ..EncodeOptions::default()
+    };
+    let x = to_bytes_with_options(&Inner { zz: 2, aa: 3 }, options).unwrap();
+    assert_eq!(x, &b"d2:zzi2e2:aai3ee"[..]);
+}
+
+#[test]
+fn test_writer() {
+    let value = crate::decode::from_bytes::<crate::Value>(b"d1:ai1e1:bl2:hiee").unwrap();
+    let mut out = Vec::new();
+    to_writer(&mut out, &value).unwrap();
+    assert_eq!(out, &b"d1:ai1e1:bl2:hiee"[..]);
+
+    // a writer that fails after a few bytes has its error reported
+    let mut buf = [0u8; 4];
+    assert!(to_writer(&mut buf[..], &value).is_err());
+}
+
+#[test]
+fn test_every_method() {
+    use std::collections::BTreeMap;
+
+    #[derive(serde::Serialize)]
+    struct Unit;
+    #[derive(serde::Serialize)]
+    struct Newtype(u8);
+    #[derive(serde::Serialize)]
+    struct Tuple(u8, char);
+    #[derive(serde::Serialize)]
+    enum E {
+        Unit,
+        Newtype(u8),
+        Tuple(u8, u8),
+        Struct { b: u8, a: u8 },
+    }
+
+    fn unsupported<T: Serialize>(value: T) -> &'static str {
+        match to_bytes(&value).unwrap_err().kind() {
+            ErrorKind::UnsupportedType { type_name } => type_name,
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    assert_eq!(unsupported(1.0f32), "f32");
+    assert_eq!(unsupported(1.0f64), "f64");
+    assert_eq!(unsupported(()), "()");
+    assert_eq!(unsupported(Unit), "Unit");
+
+    assert_eq!(to_bytes(&-1i8).unwrap(), b"i-1e");
+    assert_eq!(to_bytes(&-1i16).unwrap(), b"i-1e");
+    assert_eq!(to_bytes(&-1i32).unwrap(), b"i-1e");
+    assert_eq!(to_bytes(&-1i64).unwrap(), b"i-1e");
+    assert_eq!(to_bytes(&-1i128).unwrap(), b"i-1e");
+    assert_eq!(to_bytes(&1u8).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&1u16).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&1u32).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&1u64).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&1u128).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&'\u{e9}').unwrap(), "2:\u{e9}".as_bytes());
+    assert_eq!(to_bytes("ab").unwrap(), b"2:ab");
+    assert_eq!(to_bytes(serde_bytes::Bytes::new(b"ab")).unwrap(), b"2:ab");
+    assert_eq!(to_bytes(&Some(1u8)).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&Newtype(1)).unwrap(), b"i1e");
+    assert_eq!(to_bytes(&vec![1u8, 2]).unwrap(), b"li1ei2ee");
+    assert_eq!(to_bytes(&(1u8, 'a')).unwrap(), b"li1e1:ae");
+    assert_eq!(to_bytes(&Tuple(1, 'a')).unwrap(), b"li1e1:ae");
+    assert_eq!(to_bytes(&E::Unit).unwrap(), b"4:Unit");
+    assert_eq!(to_bytes(&E::Newtype(1)).unwrap(), b"d7:Newtypei1ee");
+    assert_eq!(to_bytes(&E::Tuple(1, 2)).unwrap(), b"d5:Tupleli1ei2eee");
+    assert_eq!(
+        to_bytes(&E::Struct { b: 1, a: 2 }).unwrap(),
+        b"d6:Structd1:ai2e1:bi1eee"
+    );
+
+    let mut map = BTreeMap::new();
+    map.insert("k", 1u8);
+    assert_eq!(to_bytes(&map).unwrap(), b"d1:ki1ee");
+}
+
+#[test]
+fn test_bool() {
+    #[derive(serde::Serialize)]
+    struct X {
+        private: bool,
+        seed: bool,
+    }
+    let x = X {
+        private: true,
+        seed: false,
+    };
+    assert_eq!(to_bytes(&x).unwrap(), b"d7:privatei1e4:seedi0ee");
+
+    let strings = EncodeOptions {
+        bool_repr: BoolRepr::String,
+        ..EncodeOptions::default()
+    };
+    let data = b"d7:private4:true4:seed5:falsee";
+    assert_eq!(to_bytes_with_options(&x, strings).unwrap(), data);
+
+    let reject = EncodeOptions {
+        bool_repr: BoolRepr::Reject,
+        ..EncodeOptions::default()
+    };
+    assert!(to_bytes_with_options(&x, reject).is_err());
+}
+
+#[test]
+fn test_option() {
+    #[derive(serde::Serialize)]
+    struct X {
+        comment: Option<String>,
+        length: Option<i64>,
+        nested: Option<Option<i64>>,
+    }
+    let x = X {
+        comment: None,
+        length: Some(7),
+        nested: Some(None),
+    };
+    assert_eq!(to_bytes(&x).unwrap(), b"d6:lengthi7ee");
+
+    let mut map = std::collections::BTreeMap::new();
+    map.insert("a", None);
+    map.insert("b", Some(1));
+    assert_eq!(to_bytes(&map).unwrap(), b"d1:bi1ee");
+
+    let err = to_bytes(&vec![Some(1), None]).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedNone);
+    let err = to_bytes(&None::<i64>).unwrap_err();
+    assert_eq!(err.kind(), &ErrorKind::UnexpectedNone);
+    assert!(to_writer(Vec::new(), &(1, None::<i64>)).is_err());
+}
+
+#[test]
+fn test_byte_array() {
+    #[derive(serde::Serialize)]
+    struct X {
+        array: serde_bytes::ByteArray<3>,
+        id: crate::NodeId,
+        list: [u8; 2],
+        pair: (u8, u8),
+    }
+    let x = X {
+        array: serde_bytes::ByteArray::new(*b"abc"),
+        id: crate::NodeId(*b"abcdefghij0123456789"),
+        list: [1, 2],
+        pair: (3, 4),
+    };
+    assert_eq!(
+        to_bytes(&x).unwrap(),
+        b"d5:array3:abc2:id20:abcdefghij01234567894:listli1ei2ee4:pairli3ei4eee".to_vec()
+    );
+    assert_eq!(to_bytes(&(1u8, 2u8)).unwrap(), b"li1ei2ee");
+}
+
+#[test]
+fn test_streaming() {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    /// collects the output where a value being encoded can see it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    /// encodes as the number of bytes written out so far
+    struct Written(Shared);
+    impl Serialize for Written {
+        fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let len = self.0 .0.borrow().len();
+            serializer.serialize_u64(len as u64)
+        }
+    }
+    #[derive(serde::Serialize)]
+    struct Sorted {
+        a: &'static str,
+        none: Option<u8>,
+        z: Written,
+    }
+    #[derive(serde::Serialize)]
+    struct Unsorted {
+        z: Written,
+        a: &'static str,
+    }
+
+    // `d1:a1:x` is out by the time `z` is encoded, its key is held back
+    // until the value shows it isn't `None`
+    let out = Shared::default();
+    let sorted = Sorted {
+        a: "x",
+        none: None,
+        z: Written(out.clone()),
+    };
+    to_writer(out.clone(), &sorted).unwrap();
+    assert_eq!(out.0.take(), b"d1:a1:x1:zi7ee");
+
+    let unsorted = Unsorted {
+        z: Written(out.clone()),
+        a: "x",
+    };
+    to_writer(out.clone(), &unsorted).unwrap();
+    assert_eq!(out.0.take(), b"d1:a1:x1:zi0ee");
+
+    let options = EncodeOptions {
+        sort_keys: false,
+        ..EncodeOptions::default()
+    };
+    to_writer_with_options(out.clone(), &unsorted, options).unwrap();
+    assert_eq!(out.0.take(), b"d1:zi1e1:a1:xe");
+}
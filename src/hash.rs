@@ -0,0 +1,131 @@
+/// fixed size identifiers that travel as raw byte strings
+use std::fmt;
+
+use serde::{de, ser, Deserialize, Serialize};
+
+macro_rules! byte_id {
+    ($(#[$meta:meta])* $name:ident, $len:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(pub [u8; $len]);
+
+        impl $name {
+            pub const LEN: usize = $len;
+
+            pub fn as_bytes(&self) -> &[u8; $len] {
+                &self.0
+            }
+
+            /// `None` unless `bytes` is exactly `LEN` long
+            pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+                let mut id = [0; $len];
+                if bytes.len() != $len {
+                    return None;
+                }
+                id.copy_from_slice(bytes);
+                Some($name(id))
+            }
+        }
+
+        impl From<[u8; $len]> for $name {
+            fn from(bytes: [u8; $len]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        /// lowercase hex, the way clients and magnet links show them
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                for byte in &self.0 {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                crate::bytes_array::serialize(&self.0, serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                crate::bytes_array::deserialize(deserializer).map($name)
+            }
+        }
+    };
+}
+
+byte_id!(
+    /// SHA-1 of the bencoded info dict, BEP 3
+    InfoHashV1,
+    20
+);
+
+byte_id!(
+    /// SHA-256 of the bencoded info dict, BEP 52
+    InfoHashV2,
+    32
+);
+
+impl InfoHashV2 {
+    /// the first 20 bytes, which is what a v2 torrent goes by in the places
+    /// sized for v1 hashes like tracker announces and the DHT
+    pub fn truncate(&self) -> InfoHashV1 {
+        let mut hash = [0; 20];
+        hash.copy_from_slice(&self.0[..20]);
+        InfoHashV1(hash)
+    }
+}
+
+byte_id!(
+    /// a DHT node id, BEP 5
+    NodeId,
+    20
+);
+
+#[test]
+fn test_ids() {
+    use crate::{decode::from_bytes, encode::to_bytes};
+    use std::collections::BTreeMap;
+
+    let hash = InfoHashV1([0xab; 20]);
+    let encoded = to_bytes(&hash).unwrap();
+    assert_eq!(&encoded[..3], b"20:");
+    assert_eq!(from_bytes::<InfoHashV1>(&encoded).unwrap(), hash);
+    assert_eq!(hash.to_string(), "ab".repeat(20));
+    assert!(from_bytes::<InfoHashV2>(&encoded).is_err());
+
+    let v2 = InfoHashV2::from_slice(&[1; 32]).unwrap();
+    assert_eq!(
+        from_bytes::<InfoHashV2>(&to_bytes(&v2).unwrap()).unwrap(),
+        v2
+    );
+    assert_eq!(NodeId::from_slice(&[0; 19]), None);
+
+    // scrape responses key their `files` dict by infohash
+    let mut files = BTreeMap::new();
+    files.insert(hash, 3);
+    let encoded = to_bytes(&files).unwrap();
+    let mut expected = b"d20:".to_vec();
+    expected.extend_from_slice(&[0xab; 20]);
+    expected.extend_from_slice(b"i3ee");
+    assert_eq!(encoded, expected);
+    assert_eq!(
+        from_bytes::<BTreeMap<InfoHashV1, i64>>(&encoded).unwrap(),
+        files
+    );
+}
@@ -1,37 +1,236 @@
 // bencode subfolder and item enum implemenation
-use std::fmt::{self, Display};
+use std::{
+    fmt::{self, Display},
+    io,
+};
 
 use serde::{de, ser};
 
+pub mod bytes_array;
 pub mod decode;
 pub mod encode;
+pub mod hash;
+pub mod metainfo;
+pub mod raw;
+pub mod read;
+pub mod spanned;
+pub mod value;
+
+pub use hash::{InfoHashV1, InfoHashV2, NodeId};
+pub use raw::{RawValue, RawValueBuf};
+pub use spanned::Spanned;
+pub use value::{Value, ValueRef};
+
+/// how `bool` maps onto bencode, which has no boolean type of its own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoolRepr {
+    /// `i0e` and `i1e`, as used by `private`, `upload_only` and friends
+    #[default]
+    Int,
+    /// the byte strings `false` and `true`
+    String,
+    /// refuse to encode or decode bools at all
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    UnexpectedEof,
+    UnexpectedToken,
+    InvalidInteger,
+    InvalidLength,
+    InvalidUtf8,
+    InvalidKey,
+    UnsortedKeys,
+    DuplicateKey,
+    TrailingBytes,
+    DepthLimitExceeded,
+    /// `None` outside a dict, where it cannot be left out like a missing entry
+    UnexpectedNone,
+    /// a Rust type with no bencode representation
+    UnsupportedType {
+        type_name: &'static str,
+    },
+    Io,
+    Custom(String),
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnexpectedEof => f.write_str("unexpected end of input"),
+            ErrorKind::UnexpectedToken => f.write_str("unexpected token"),
+            ErrorKind::InvalidInteger => f.write_str("invalid integer"),
+            ErrorKind::InvalidLength => f.write_str("invalid length"),
+            ErrorKind::InvalidUtf8 => f.write_str("invalid utf-8"),
+            ErrorKind::InvalidKey => f.write_str("dict key must be a byte string"),
+            ErrorKind::UnsortedKeys => f.write_str("unsorted dict keys"),
+            ErrorKind::DuplicateKey => f.write_str("duplicate dict key"),
+            ErrorKind::TrailingBytes => f.write_str("trailing bytes"),
+            ErrorKind::DepthLimitExceeded => f.write_str("nesting too deep"),
+            ErrorKind::UnexpectedNone => {
+                f.write_str("`None` can only be encoded as a missing dict entry")
+            }
+            ErrorKind::UnsupportedType { type_name } => {
+                write!(f, "unsupported type `{}`", type_name)
+            }
+            ErrorKind::Io => f.write_str("i/o error"),
+            ErrorKind::Custom(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// one step of the path from the document root to a failing value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(Vec<u8>),
+    Index(usize),
+}
+
+/// boxed so `Result<T, Error>` stays a pointer wide on the happy path
+#[derive(Debug)]
+pub struct Error {
+    inner: Box<ErrorImpl>,
+}
 
 #[derive(Debug)]
-pub enum Error {
-    Message(String),
-    Unimplemented,
-    Overflow,
+struct ErrorImpl {
+    kind: ErrorKind,
+    offset: Option<usize>,
+    path: Vec<PathSegment>,
+    expected: Option<&'static str>,
+    found: Option<String>,
+    source: Option<io::Error>,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind) -> Self {
+        Error {
+            inner: Box::new(ErrorImpl {
+                kind,
+                offset: None,
+                path: Vec::new(),
+                expected: None,
+                found: None,
+                source: None,
+            }),
+        }
+    }
+
+    pub(crate) fn message<T: Display>(msg: T) -> Self {
+        Error::new(ErrorKind::Custom(msg.to_string()))
+    }
+
+    pub(crate) fn unsupported(type_name: &'static str) -> Self {
+        Error::new(ErrorKind::UnsupportedType { type_name })
+    }
+
+    pub(crate) fn io(err: io::Error) -> Self {
+        let mut error = Error::new(ErrorKind::Io);
+        error.inner.source = Some(err);
+        error
+    }
+
+    pub(crate) fn expected(mut self, expected: &'static str) -> Self {
+        self.inner.expected = Some(expected);
+        self
+    }
+
+    pub(crate) fn found<T: Display>(mut self, found: T) -> Self {
+        self.inner.found = Some(found.to_string());
+        self
+    }
+
+    /// records where the error happened unless a more precise offset is known
+    pub(crate) fn or_offset(mut self, offset: usize) -> Self {
+        self.inner.offset.get_or_insert(offset);
+        self
+    }
+
+    /// prepends the dict key or list index the error bubbled up through
+    pub(crate) fn within(mut self, segment: PathSegment) -> Self {
+        self.inner.path.insert(0, segment);
+        self
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.inner.kind
+    }
+
+    /// byte offset into the input, for decoding errors
+    pub fn offset(&self) -> Option<usize> {
+        self.inner.offset
+    }
+
+    pub fn path(&self) -> &[PathSegment] {
+        &self.inner.path
+    }
+
+    /// the path formatted like `info.files[3].length`
+    pub fn path_string(&self) -> String {
+        let mut out = String::new();
+        for segment in &self.inner.path {
+            match segment {
+                PathSegment::Key(key) => {
+                    if !out.is_empty() {
+                        out.push('.');
+                    }
+                    out.push_str(&String::from_utf8_lossy(key));
+                }
+                PathSegment::Index(index) => out.push_str(&format!("[{}]", index)),
+            }
+        }
+        out
+    }
+
+    pub fn expected_token(&self) -> Option<&'static str> {
+        self.inner.expected
+    }
+
+    pub fn found_token(&self) -> Option<&str> {
+        self.inner.found.as_deref()
+    }
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> fmt::Result {
-        match self {
-            Error::Message(msg) => f.write_str(msg),
-            Error::Unimplemented => f.write_str("Primitive is unimplemented"),
-            Error::Overflow => f.write_str("Integer overflow"),
+        Display::fmt(&self.inner.kind, f)?;
+        if let Some(source) = &self.inner.source {
+            write!(f, ": {}", source)?;
+        }
+        match (self.inner.expected, &self.inner.found) {
+            (Some(expected), Some(found)) => write!(f, ", expected {}, found {}", expected, found)?,
+            (Some(expected), None) => write!(f, ", expected {}", expected)?,
+            (None, Some(found)) => write!(f, ", found {}", found)?,
+            (None, None) => {}
+        }
+        if let Some(offset) = self.inner.offset {
+            write!(f, " at offset {}", offset)?;
         }
+        if !self.inner.path.is_empty() {
+            write!(f, " in `{}`", self.path_string())?;
+        }
+        Ok(())
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner
+            .source
+            .as_ref()
+            .map(|e| e as &(dyn std::error::Error + 'static))
+    }
+}
 
 impl ser::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        Error::message(msg)
     }
 }
 impl de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        Error::message(msg)
     }
 }
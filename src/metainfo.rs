@@ -0,0 +1,723 @@
+/// typed `.torrent` files: BEP 3 with the BEP 12 announce-list, and BEP 52 v2
+use std::{collections::BTreeMap, fmt};
+
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_bytes::{ByteArray, ByteBuf};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{decode, encode, Error, InfoHashV1, InfoHashV2, RawValue, Value};
+
+pub mod create;
+pub mod verify;
+
+pub use create::{create_torrent, CreateOptions};
+pub use verify::{verify, FileReport, FileStatus, Verification};
+
+/// keys the typed fields don't know about, kept so they survive a decode and
+/// encode. that only gives back the exact bytes for canonical input, the map
+/// sorts keys and lenient decoding keeps just one of a duplicated key
+pub type Extra = BTreeMap<ByteBuf, Value>;
+
+/// BEP 52 merkle layers keyed by the `pieces root` of each file bigger than
+/// a piece, every value is the concatenated 32 byte hashes of its pieces
+pub type PieceLayers = BTreeMap<ByteArray<32>, ByteBuf>;
+
+/// every file's path components, as bytes since v1 torrents don't have to
+/// use utf-8, along with its length
+pub type FileList<'a> = Vec<(Vec<&'a [u8]>, u64)>;
+
+/// v2 block size, the leaves of every merkle tree hash this many bytes
+pub const BLOCK_SIZE: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+    /// both `pieces` and a `file tree`, describing the same files
+    Hybrid,
+}
+
+/// text fields are kept as byte strings, older torrents encode them in
+/// whatever character set `encoding` names. the `_str` accessors give them
+/// as `str` when they are utf-8
+///
+/// encoding writes canonical bencode, so a file with unsorted or duplicate
+/// keys comes back different. hash the original with `info_span` and friends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Torrent {
+    pub announce: Option<ByteBuf>,
+    /// tiers of trackers, tried in order with the urls in a tier shuffled
+    #[serde(rename = "announce-list")]
+    pub announce_list: Option<Vec<Vec<ByteBuf>>>,
+    /// seconds since the unix epoch
+    #[serde(rename = "creation date")]
+    pub creation_date: Option<i64>,
+    pub comment: Option<ByteBuf>,
+    #[serde(rename = "created by")]
+    pub created_by: Option<ByteBuf>,
+    /// character set of the strings in `info`, hardly used since utf-8 won
+    pub encoding: Option<ByteBuf>,
+    pub info: Info,
+    #[serde(rename = "piece layers")]
+    pub piece_layers: Option<PieceLayers>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Info {
+    /// file name in single-file mode, directory name in multi-file mode
+    pub name: ByteBuf,
+    #[serde(rename = "piece length")]
+    pub piece_length: u64,
+    /// SHA-1 hashes of every piece, 20 bytes each, missing in v2-only torrents
+    #[serde(default, with = "serde_bytes")]
+    pub pieces: Option<Vec<u8>>,
+    /// only in single-file v1 torrents
+    pub length: Option<u64>,
+    /// only in multi-file v1 torrents
+    pub files: Option<Vec<File>>,
+    /// 2 for v2 and hybrid torrents
+    #[serde(rename = "meta version")]
+    pub meta_version: Option<u64>,
+    /// only in v2 and hybrid torrents
+    #[serde(rename = "file tree")]
+    pub file_tree: Option<FileTree>,
+    /// BEP 27, only use the trackers listed here
+    #[serde(default, deserialize_with = "decode::option_bool_from_any")]
+    pub private: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct File {
+    pub length: u64,
+    /// path components below the torrent's directory
+    pub path: Vec<ByteBuf>,
+    /// BEP 47 flags, `p` marks the padding files hybrid torrents insert
+    pub attr: Option<ByteBuf>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl File {
+    pub fn is_padding(&self) -> bool {
+        self.attr.as_ref().is_some_and(|attr| attr.contains(&b'p'))
+    }
+
+    /// `None` if any component isn't utf-8
+    pub fn path_str(&self) -> Option<Vec<&str>> {
+        self.path.iter().map(|component| text(component)).collect()
+    }
+}
+
+/// one directory of a BEP 52 file tree, entries sorted by name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileTree(pub BTreeMap<String, FileTreeNode>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileTreeNode {
+    /// stored as a dict whose only key is the empty string
+    File(TreeFile),
+    Dir(FileTree),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeFile {
+    pub length: u64,
+    /// root of the merkle tree over the file's blocks, absent if it's empty
+    #[serde(rename = "pieces root")]
+    pub pieces_root: Option<ByteArray<32>>,
+    #[serde(flatten)]
+    pub extra: Extra,
+}
+
+impl FileTree {
+    /// every file depth first in key order, which is also the order their
+    /// data is laid out in for hybrid torrents
+    pub fn files(&self) -> Vec<(Vec<&str>, &TreeFile)> {
+        let mut files = Vec::new();
+        self.collect(&mut Vec::new(), &mut files);
+        files
+    }
+
+    /// single-file torrents key their one file by the torrent name
+    fn is_single(&self, name: &[u8]) -> bool {
+        let file = text(name).and_then(|name| self.0.get(name));
+        self.0.len() == 1 && matches!(file, Some(FileTreeNode::File(_)))
+    }
+
+    fn collect<'a>(
+        &'a self,
+        dir: &mut Vec<&'a str>,
+        files: &mut Vec<(Vec<&'a str>, &'a TreeFile)>,
+    ) {
+        for (name, node) in &self.0 {
+            dir.push(name);
+            match node {
+                FileTreeNode::File(file) => files.push((dir.clone(), file)),
+                FileTreeNode::Dir(tree) => tree.collect(dir, files),
+            }
+            dir.pop();
+        }
+    }
+}
+
+impl Torrent {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        decode::from_bytes(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        encode::to_bytes(self)
+    }
+
+    /// `Info::validate` plus a piece layer for every v2 file bigger than a
+    /// piece, of the right size and hashing up to the file's `pieces root`
+    pub fn validate(&self) -> Result<(), Error> {
+        self.info.validate()?;
+        let tree = match &self.info.file_tree {
+            Some(tree) => tree,
+            None => return Ok(()),
+        };
+        let empty = PieceLayers::new();
+        let layers = self.piece_layers.as_ref().unwrap_or(&empty);
+        for (path, file) in tree.files() {
+            if file.length <= self.info.piece_length {
+                continue;
+            }
+            let pieces = file.length.div_ceil(self.info.piece_length);
+            let root = file.pieces_root.as_ref();
+            let layer = root.and_then(|root| layers.get(root).map(|layer| (root, layer)));
+            let problem = match layer {
+                Some((_, layer)) if layer.len() as u64 != pieces * 32 => "has the wrong size",
+                // a layer that doesn't hash up to the root can't be trusted to
+                // check pieces with
+                Some((root, layer))
+                    if create::layer_root(layer, self.info.piece_length) != **root =>
+                {
+                    "doesn't match its pieces root"
+                }
+                Some(_) => continue,
+                None => "is missing",
+            };
+            return Err(Error::message(format!(
+                "piece layer of `{}` {}",
+                path.join("/"),
+                problem
+            )));
+        }
+        Ok(())
+    }
+
+    /// every tracker tier, falling back to `announce` when there is no
+    /// announce-list as BEP 12 says. urls that aren't utf-8 are left out
+    pub fn trackers(&self) -> Vec<Vec<&str>> {
+        let tiers = match (&self.announce_list, &self.announce) {
+            (Some(tiers), _) if !tiers.is_empty() => tiers.iter().map(|tier| &tier[..]).collect(),
+            (_, Some(announce)) => vec![std::slice::from_ref(announce)],
+            _ => Vec::new(),
+        };
+        tiers
+            .into_iter()
+            .map(|tier| tier.iter().filter_map(|url| text(url)).collect::<Vec<_>>())
+            .filter(|tier| !tier.is_empty())
+            .collect()
+    }
+
+    pub fn announce_str(&self) -> Option<&str> {
+        self.announce.as_ref().and_then(|announce| text(announce))
+    }
+
+    pub fn comment_str(&self) -> Option<&str> {
+        self.comment.as_ref().and_then(|comment| text(comment))
+    }
+
+    pub fn created_by_str(&self) -> Option<&str> {
+        self.created_by
+            .as_ref()
+            .and_then(|created_by| text(created_by))
+    }
+
+    pub fn encoding_str(&self) -> Option<&str> {
+        self.encoding.as_ref().and_then(|encoding| text(encoding))
+    }
+}
+
+impl Info {
+    /// `None` if the name isn't utf-8
+    pub fn name_str(&self) -> Option<&str> {
+        text(&self.name)
+    }
+
+    pub fn version(&self) -> Version {
+        match (self.meta_version, &self.pieces) {
+            (Some(2), Some(_)) => Version::Hybrid,
+            (Some(2), None) => Version::V2,
+            _ => Version::V1,
+        }
+    }
+
+    /// a single file at the root, for v2 that's a file tree holding one file
+    /// named like the torrent
+    pub fn is_single_file(&self) -> bool {
+        match self.version() {
+            Version::V2 => self
+                .file_tree
+                .as_ref()
+                .is_some_and(|tree| tree.is_single(&self.name)),
+            _ => self.files.is_none(),
+        }
+    }
+
+    /// the files in the order their bytes make up the pieces, with paths
+    /// starting at `name`. that's `files` including any padding for v1 and
+    /// hybrid torrents and the file tree for v2
+    pub fn file_list(&self) -> FileList<'_> {
+        match self.version() {
+            Version::V2 => self.v2_file_list().unwrap_or_default(),
+            _ => self
+                .v1_files()
+                .into_iter()
+                .map(|(path, length, _)| (path, length))
+                .collect(),
+        }
+    }
+
+    /// the v2 file tree flattened, with paths starting at `name` the same way
+    /// as `file_list`
+    pub fn v2_file_list(&self) -> Option<FileList<'_>> {
+        let tree = self.file_tree.as_ref()?;
+        let single = tree.is_single(&self.name);
+        let files = tree
+            .files()
+            .into_iter()
+            .map(|(path, file)| {
+                let mut components = Vec::new();
+                if !single {
+                    components.push(&self.name[..]);
+                }
+                components.extend(path.into_iter().map(str::as_bytes));
+                (components, file.length)
+            })
+            .collect();
+        Some(files)
+    }
+
+    /// the v1 files as path, length and whether it's padding
+    pub(crate) fn v1_files(&self) -> Vec<(Vec<&[u8]>, u64, bool)> {
+        match &self.files {
+            Some(files) => files
+                .iter()
+                .map(|file| {
+                    let mut path = vec![&self.name[..]];
+                    path.extend(file.path.iter().map(|component| &component[..]));
+                    (path, file.length, file.is_padding())
+                })
+                .collect(),
+            None => vec![(vec![&self.name[..]], self.length.unwrap_or(0), false)],
+        }
+    }
+
+    pub fn total_length(&self) -> u64 {
+        self.file_list().iter().map(|(_, length)| length).sum()
+    }
+
+    /// the v1 piece hashes, none for v2-only torrents
+    pub fn piece_hashes(&self) -> impl Iterator<Item = &[u8]> {
+        self.pieces.as_deref().unwrap_or_default().chunks(20)
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.as_ref().map_or(0, |pieces| pieces.len() / 20)
+    }
+
+    /// checks the info dict is consistent with its version: one of `length`
+    /// or `files` and a hash for every piece for v1, a file tree and a sane
+    /// piece length for v2, and for hybrids the same files in the same order
+    /// in both `files` and `file tree`
+    pub fn validate(&self) -> Result<(), Error> {
+        let version = self.version();
+        if self.piece_length == 0 {
+            return Err(Error::message("piece length must not be zero"));
+        }
+        if version != Version::V1 {
+            if self.file_tree.is_none() {
+                return Err(Error::message("v2 torrent without a file tree"));
+            }
+            if self.piece_length < BLOCK_SIZE || !self.piece_length.is_power_of_two() {
+                return Err(Error::message(
+                    "v2 piece length must be a power of two of at least 16 KiB",
+                ));
+            }
+        }
+        if version == Version::V2 {
+            return Ok(());
+        }
+
+        if self.length.is_some() == self.files.is_some() {
+            return Err(Error::message(
+                "v1 torrent needs exactly one of length and files",
+            ));
+        }
+        let pieces = self.pieces.as_deref().unwrap_or_default();
+        let total = self.total_length();
+        if !pieces.len().is_multiple_of(20)
+            || (pieces.len() / 20) as u64 != total.div_ceil(self.piece_length)
+        {
+            return Err(Error::message(
+                "pieces must hold one 20 byte hash per piece",
+            ));
+        }
+
+        if version == Version::Hybrid {
+            let v1: Vec<_> = self
+                .v1_files()
+                .into_iter()
+                .filter(|(_, _, padding)| !padding)
+                .map(|(path, length, _)| (path, length))
+                .collect();
+            if Some(v1) != self.v2_file_list() {
+                return Err(Error::message(
+                    "hybrid torrent lists different files in files and file tree",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn text(bytes: &[u8]) -> Option<&str> {
+    std::str::from_utf8(bytes).ok()
+}
+
+/// the `info` dict exactly as it appears in a `.torrent` file, which is what
+/// the infohashes are taken over. re-encoding a decoded `Info` would only give
+/// the same bytes if the file was canonical to begin with
+pub fn info_span(torrent: &[u8]) -> Result<&[u8], Error> {
+    #[derive(Deserialize)]
+    struct Span<'a> {
+        #[serde(borrow)]
+        info: RawValue<'a>,
+    }
+    let span: Span = decode::from_bytes(torrent)?;
+    Ok(span.info.as_bytes())
+}
+
+/// SHA-1 of the info dict, BEP 3
+pub fn info_hash_v1(torrent: &[u8]) -> Result<InfoHashV1, Error> {
+    let digest = Sha1::digest(info_span(torrent)?);
+    Ok(InfoHashV1(digest.into()))
+}
+
+/// SHA-256 of the info dict, BEP 52
+pub fn info_hash_v2(torrent: &[u8]) -> Result<InfoHashV2, Error> {
+    let digest = Sha256::digest(info_span(torrent)?);
+    Ok(InfoHashV2(digest.into()))
+}
+
+/// the v2 hash cut down to 20 bytes, see `InfoHashV2::truncate`
+pub fn info_hash_v2_truncated(torrent: &[u8]) -> Result<InfoHashV1, Error> {
+    info_hash_v2(torrent).map(|hash| hash.truncate())
+}
+
+// ---- SERDE ----
+
+impl Serialize for FileTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, node) in &self.0 {
+            map.serialize_entry(name, node)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for FileTreeNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FileTreeNode::File(file) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("", file)?;
+                map.end()
+            }
+            FileTreeNode::Dir(tree) => tree.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match FileTreeNode::deserialize(deserializer)? {
+            FileTreeNode::Dir(tree) => Ok(tree),
+            FileTreeNode::File(_) => Err(de::Error::custom("file tree root is a file")),
+        }
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = FileTreeNode;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a file tree dict")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<FileTreeNode, A::Error> {
+        let mut dir = BTreeMap::new();
+        while let Some(name) = map.next_key::<String>()? {
+            if !name.is_empty() {
+                dir.insert(name, map.next_value()?);
+                continue;
+            }
+            // `""` sorts first, so a file can't have picked up entries yet
+            let file = map.next_value()?;
+            if !dir.is_empty() || map.next_key::<de::IgnoredAny>()?.is_some() {
+                return Err(de::Error::custom(
+                    "file tree entry is both a file and a directory",
+                ));
+            }
+            return Ok(FileTreeNode::File(file));
+        }
+        Ok(FileTreeNode::Dir(FileTree(dir)))
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTreeNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(NodeVisitor)
+    }
+}
+
+#[test]
+fn test_metainfo() {
+    let mut data = b"d8:announce23:http://tracker/announce13:announce-listll23:http://tracker/announceel17:udp://backup:6969ee7:comment4:test10:created by6:mktorr13:creation datei1700000000e4:infod5:filesld6:lengthi3e4:pathl1:aee\
+d6:lengthi5e6:md5sum32:00000000000000000000000000000000\
+4:pathl3:sub1:beee4:name3:dir12:piece lengthi16384e6:pieces20:"
+        .to_vec();
+    data.extend_from_slice(&[0x5a; 20]);
+    data.extend_from_slice(b"7:privatei1e6:source3:abce8:url-listl12:http://seed/ee");
+
+    let torrent = Torrent::from_bytes(&data).unwrap();
+    assert_eq!(torrent.creation_date, Some(1_700_000_000));
+    assert_eq!(torrent.trackers().len(), 2);
+    assert_eq!(torrent.info.private, Some(true));
+    assert_eq!(torrent.info.total_length(), 8);
+    assert_eq!(torrent.info.piece_count(), 1);
+    assert_eq!(
+        torrent.info.file_list(),
+        [
+            (vec![&b"dir"[..], b"a"], 3),
+            (vec![&b"dir"[..], b"sub", b"b"], 5)
+        ]
+    );
+    let files = torrent.info.files.as_ref().unwrap();
+    assert!(files[1].extra.contains_key(&ByteBuf::from("md5sum")));
+    assert_eq!(
+        torrent.info.extra[&ByteBuf::from("source")].as_str(),
+        Some("abc")
+    );
+    assert!(torrent.extra.contains_key(&ByteBuf::from("url-list")));
+    assert_eq!(torrent.to_bytes().unwrap(), data);
+
+    let mut single = b"d4:infod6:lengthi7e4:name5:a.txt12:piece lengthi32768e6:pieces20:".to_vec();
+    single.extend_from_slice(&[0; 20]);
+    single.extend_from_slice(b"ee");
+    let torrent = Torrent::from_bytes(&single).unwrap();
+    assert!(torrent.info.is_single_file());
+    assert_eq!(torrent.info.file_list(), [(vec![&b"a.txt"[..]], 7)]);
+    assert!(torrent.trackers().is_empty());
+    assert_eq!(torrent.info.private, None);
+    assert_eq!(torrent.to_bytes().unwrap(), single);
+
+    // older torrents use whatever character set `encoding` names, GBK here,
+    // and still have to decode and encode back to the same bytes
+    let mut legacy = b"d7:comment4:\xc4\xe3\xba\xc38:encoding3:GBK4:infod5:filesld6:lengthi1e4:pathl4:\xce\xc4\xbc\xfeeee\
+4:name4:\xb2\xe2\xca\xd412:piece lengthi16384e6:pieces20:"
+        .to_vec();
+    legacy.extend_from_slice(&[1; 20]);
+    legacy.extend_from_slice(b"ee");
+    let torrent = Torrent::from_bytes(&legacy).unwrap();
+    torrent.validate().unwrap();
+    assert_eq!(&torrent.info.name[..], b"\xb2\xe2\xca\xd4");
+    assert_eq!(torrent.info.name_str(), None);
+    assert_eq!(torrent.comment_str(), None);
+    assert_eq!(torrent.encoding_str(), Some("GBK"));
+    assert_eq!(torrent.info.files.as_ref().unwrap()[0].path_str(), None);
+    assert_eq!(
+        torrent.info.file_list(),
+        [(vec![&b"\xb2\xe2\xca\xd4"[..], b"\xce\xc4\xbc\xfe"], 1)]
+    );
+    assert_eq!(torrent.to_bytes().unwrap(), legacy);
+    assert!(info_hash_v1(&legacy).is_ok());
+}
+
+#[test]
+fn test_v2() {
+    let mut single = b"d4:infod9:file treed5:a.txtd0:d6:lengthi5e11:pieces root32:".to_vec();
+    single.extend_from_slice(&[7; 32]);
+    single.extend_from_slice(b"eee12:meta versioni2e4:name5:a.txt12:piece lengthi16384eee");
+    let torrent = Torrent::from_bytes(&single).unwrap();
+    assert_eq!(torrent.info.version(), Version::V2);
+    assert!(torrent.info.is_single_file());
+    assert_eq!(torrent.info.file_list(), [(vec![&b"a.txt"[..]], 5)]);
+    assert_eq!(torrent.info.piece_count(), 0);
+    torrent.validate().unwrap();
+    assert_eq!(torrent.to_bytes().unwrap(), single);
+
+    let leaf = |length, root: [u8; 32]| {
+        FileTreeNode::File(TreeFile {
+            length,
+            pieces_root: Some(ByteArray::new(root)),
+            extra: Extra::new(),
+        })
+    };
+    let file = |path: &[&str], length, attr: Option<&str>| File {
+        length,
+        path: path.iter().map(|&p| ByteBuf::from(p)).collect(),
+        attr: attr.map(ByteBuf::from),
+        extra: Extra::new(),
+    };
+    // `b` has two pieces, so its root is the hash of the two in its layer
+    let b_root: [u8; 32] = Sha256::digest([0; 64]).into();
+    let mut sub = FileTree::default();
+    sub.0.insert("b".to_string(), leaf(20000, b_root));
+    let mut tree = FileTree::default();
+    tree.0.insert("a".to_string(), leaf(3, [1; 32]));
+    tree.0.insert("sub".to_string(), FileTreeNode::Dir(sub));
+    let mut layers = PieceLayers::new();
+    layers.insert(ByteArray::new(b_root), ByteBuf::from(vec![0; 64]));
+
+    let mut hybrid = Torrent {
+        announce: None,
+        announce_list: None,
+        creation_date: None,
+        comment: None,
+        created_by: None,
+        encoding: None,
+        info: Info {
+            name: ByteBuf::from("dir"),
+            piece_length: 16384,
+            pieces: Some(vec![0; 60]),
+            length: None,
+            files: Some(vec![
+                file(&["a"], 3, None),
+                file(&[".pad", "16381"], 16381, Some("p")),
+                file(&["sub", "b"], 20000, None),
+            ]),
+            meta_version: Some(2),
+            file_tree: Some(tree),
+            private: None,
+            extra: Extra::new(),
+        },
+        piece_layers: Some(layers),
+        extra: Extra::new(),
+    };
+    assert_eq!(hybrid.info.version(), Version::Hybrid);
+    hybrid.validate().unwrap();
+    assert_eq!(
+        hybrid.info.v2_file_list().unwrap(),
+        [
+            (vec![&b"dir"[..], b"a"], 3),
+            (vec![&b"dir"[..], b"sub", b"b"], 20000)
+        ]
+    );
+    assert_eq!(hybrid.info.file_list().len(), 3);
+    assert_eq!(hybrid.info.total_length(), 36384);
+
+    let encoded = hybrid.to_bytes().unwrap();
+    assert_eq!(Torrent::from_bytes(&encoded).unwrap(), hybrid);
+
+    let mut layerless = hybrid.clone();
+    layerless.piece_layers = None;
+    assert!(layerless.info.validate().is_ok());
+    assert!(layerless.validate().is_err());
+
+    // the right size, but not what the root was taken over
+    let mut forged = hybrid.clone();
+    let layers = forged.piece_layers.as_mut().unwrap();
+    layers.insert(ByteArray::new(b_root), ByteBuf::from(vec![9; 64]));
+    assert!(forged.info.validate().is_ok());
+    assert!(forged.validate().is_err());
+
+    let mut mismatched = hybrid.clone();
+    let files = mismatched.info.files.as_mut().unwrap();
+    files.swap(0, 2);
+    assert!(mismatched.info.validate().is_err());
+
+    hybrid.info.piece_length = 10000;
+    assert!(hybrid.info.validate().is_err());
+
+    // a dict can't be a file and a directory at once
+    let mut both = b"d4:infod9:file treed1:ad0:d6:lengthi0ee1:bdeee".to_vec();
+    both.extend_from_slice(b"12:meta versioni2e4:name1:x12:piece lengthi16384eee");
+    assert!(Torrent::from_bytes(&both).is_err());
+}
+
+#[test]
+fn test_info_hash() {
+    // `name` before `length` isn't canonical, the hash has to be taken over
+    // these bytes as they are rather than a sorted re-encoding
+    let mut info = b"d4:name5:a.txt6:lengthi7e12:piece lengthi32768e6:pieces20:".to_vec();
+    info.extend_from_slice(&[0; 20]);
+    info.push(b'e');
+    let mut data = b"d7:comment3:abc4:info".to_vec();
+    data.extend_from_slice(&info);
+    data.extend_from_slice(b"e");
+
+    assert_eq!(info_span(&data).unwrap(), &info[..]);
+    let v1 = info_hash_v1(&data).unwrap();
+    assert_eq!(v1.to_string(), "5fc39b649f19e9972875c54bf61d85e937521520");
+    let v2 = info_hash_v2(&data).unwrap();
+    assert_eq!(
+        v2.to_string(),
+        "1eefb2cace2a0092c9186ee2fc8bfab3616baf40bda7c4494c5592fc9be7b929"
+    );
+    let truncated = info_hash_v2_truncated(&data).unwrap();
+    assert_eq!(
+        truncated.to_string(),
+        "1eefb2cace2a0092c9186ee2fc8bfab3616baf40"
+    );
+    assert_eq!(truncated, v2.truncate());
+
+    let reencoded = encode::to_bytes(&Torrent::from_bytes(&data).unwrap()).unwrap();
+    assert_ne!(info_hash_v1(&reencoded).unwrap(), v1);
+
+    assert!(info_hash_v1(b"d7:comment3:abce").is_err());
+    assert!(info_hash_v1(&data[..data.len() - 1]).is_err());
+
+    // a hybrid torrent with padding and a piece layer, written together with
+    // its hashes by the standalone tests/fixtures/hybrid.py
+    let data = include_bytes!("../tests/fixtures/hybrid.torrent");
+    let torrent = Torrent::from_bytes(data).unwrap();
+    torrent.validate().unwrap();
+    assert_eq!(torrent.info.version(), Version::Hybrid);
+    assert_eq!(torrent.info.name_str(), Some("hybrid-test"));
+    assert_eq!(
+        torrent.info.v2_file_list().unwrap(),
+        [
+            (vec![&b"hybrid-test"[..], b"a.txt"], 20000),
+            (vec![&b"hybrid-test"[..], b"sub", b"b.bin"], 70000)
+        ]
+    );
+    assert_eq!(torrent.to_bytes().unwrap(), &data[..]);
+    assert_eq!(
+        info_hash_v1(data).unwrap().to_string(),
+        "5c3371313f9786b10497f8052bc8cace76ed8a5e"
+    );
+    assert_eq!(
+        info_hash_v2(data).unwrap().to_string(),
+        "4bfc1d5c37af3eeddf7f6989f74215de16a7ec8d450a286059a34c706244e7b9"
+    );
+    assert_eq!(
+        info_hash_v2_truncated(data).unwrap().to_string(),
+        "4bfc1d5c37af3eeddf7f6989f74215de16a7ec8d"
+    );
+}
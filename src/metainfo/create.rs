@@ -0,0 +1,685 @@
+/// building new torrents from files on disk
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use serde_bytes::{ByteArray, ByteBuf};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use super::{
+    Extra, File, FileTree, FileTreeNode, Info, PieceLayers, Torrent, TreeFile, Version, BLOCK_SIZE,
+};
+use crate::{Error, Value};
+
+/// everything about a new torrent that isn't decided by the files themselves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateOptions {
+    pub version: Version,
+    /// picked from the total size when `None`, v2 and hybrid torrents need a
+    /// power of two of at least 16 KiB
+    pub piece_length: Option<u64>,
+    /// tracker tiers, the first url becomes `announce`
+    pub trackers: Vec<Vec<String>>,
+    /// BEP 19 `url-list`
+    pub web_seeds: Vec<String>,
+    pub private: bool,
+    /// goes into the info dict, so the same files with different sources get
+    /// different infohashes
+    pub source: Option<String>,
+    pub comment: Option<String>,
+    pub created_by: Option<String>,
+    /// left out unless set so that creating a torrent is reproducible
+    pub creation_date: Option<i64>,
+    /// align every file to a piece boundary with BEP 47 padding files, which
+    /// hybrid torrents always do
+    pub pad_files: bool,
+    /// defaults to the file or directory name
+    pub name: Option<String>,
+}
+
+impl Default for CreateOptions {
+    fn default() -> Self {
+        CreateOptions {
+            version: Version::V1,
+            piece_length: None,
+            trackers: Vec::new(),
+            web_seeds: Vec::new(),
+            private: false,
+            source: None,
+            comment: None,
+            created_by: None,
+            creation_date: None,
+            pad_files: false,
+            name: None,
+        }
+    }
+}
+
+/// a piece length giving somewhere around 1000 to 2000 pieces, between
+/// 16 KiB and 16 MiB
+pub fn auto_piece_length(total_length: u64) -> u64 {
+    (total_length / 1500)
+        .next_power_of_two()
+        .clamp(BLOCK_SIZE, 16 * 1024 * 1024)
+}
+
+/// hashes `path`, a single file or a directory walked recursively, into a
+/// torrent. files are ordered by their path so the same tree always gives the
+/// same torrent. symbolic links below a directory are skipped
+pub fn create_torrent(path: &Path, options: &CreateOptions) -> Result<Torrent, Error> {
+    let name = match &options.name {
+        Some(name) => name.clone(),
+        None => utf8_name(path.file_name().map(Path::new).unwrap_or(path))?,
+    };
+    let single = fs::metadata(path).map_err(Error::io)?.is_file();
+    let mut sources = Vec::new();
+    if single {
+        sources.push((Vec::new(), path.to_path_buf()));
+    } else {
+        walk(path, &mut Vec::new(), &mut sources)?;
+        if sources.is_empty() {
+            return Err(Error::message(format!(
+                "`{}` has no files in it",
+                path.display()
+            )));
+        }
+        sources.sort();
+    }
+
+    let mut files = Vec::new();
+    for (components, path) in sources {
+        let length = fs::metadata(&path).map_err(Error::io)?.len();
+        files.push(Source {
+            components,
+            path,
+            length,
+        });
+    }
+    let total: u64 = files.iter().map(|file| file.length).sum();
+
+    let piece_length = options
+        .piece_length
+        .unwrap_or_else(|| auto_piece_length(total));
+    let v1 = options.version != Version::V2;
+    let v2 = options.version != Version::V1;
+    if piece_length == 0 {
+        return Err(Error::message("piece length must not be zero"));
+    }
+    if v2 && (piece_length < BLOCK_SIZE || !piece_length.is_power_of_two()) {
+        return Err(Error::message(
+            "v2 piece length must be a power of two of at least 16 KiB",
+        ));
+    }
+    let pad = options.pad_files || options.version == Version::Hybrid;
+
+    let mut pieces = PieceHasher::new(piece_length);
+    let mut v1_files = Vec::new();
+    let mut tree = FileTree::default();
+    let mut layers = PieceLayers::new();
+    let count = files.len();
+    for (i, file) in files.into_iter().enumerate() {
+        let mut tree_hasher = if v2 {
+            Some(MerkleHasher::new(piece_length))
+        } else {
+            None
+        };
+        hash_file(&file.path, file.length, |block| {
+            if v1 {
+                pieces.update(block);
+            }
+            if let Some(tree_hasher) = &mut tree_hasher {
+                tree_hasher.update(block);
+            }
+        })?;
+
+        if v1 {
+            v1_files.push(new_file(file.length, &file.components, None));
+            let rem = file.length % piece_length;
+            if pad && rem != 0 && i + 1 < count {
+                let padding = piece_length - rem;
+                pieces.update_zeros(padding);
+                let path = [".pad".to_string(), padding.to_string()];
+                v1_files.push(new_file(padding, &path, Some("p")));
+            }
+        }
+
+        if v2 {
+            let pieces_root = if file.length == 0 {
+                None
+            } else {
+                let (root, layer) = tree_hasher.map(MerkleHasher::finish).unwrap_or_default();
+                if file.length > piece_length {
+                    layers.insert(ByteArray::new(root), ByteBuf::from(layer));
+                }
+                Some(ByteArray::new(root))
+            };
+            let leaf = TreeFile {
+                length: file.length,
+                pieces_root,
+                extra: Extra::new(),
+            };
+            let components = if single {
+                vec![name.clone()]
+            } else {
+                file.components
+            };
+            insert(&mut tree, &components, leaf);
+        }
+    }
+
+    let mut info_extra = Extra::new();
+    if let Some(source) = &options.source {
+        info_extra.insert(ByteBuf::from("source"), Value::from(source.as_str()));
+    }
+    let info = Info {
+        name: ByteBuf::from(name),
+        piece_length,
+        pieces: if v1 { Some(pieces.finish()) } else { None },
+        length: if v1 && single { Some(total) } else { None },
+        files: if v1 && !single { Some(v1_files) } else { None },
+        meta_version: if v2 { Some(2) } else { None },
+        file_tree: if v2 { Some(tree) } else { None },
+        private: if options.private { Some(true) } else { None },
+        extra: info_extra,
+    };
+
+    let mut extra = Extra::new();
+    if !options.web_seeds.is_empty() {
+        let seeds = options.web_seeds.iter().map(|s| Value::from(s.as_str()));
+        extra.insert(ByteBuf::from("url-list"), Value::List(seeds.collect()));
+    }
+    let tracker_count: usize = options.trackers.iter().map(Vec::len).sum();
+    let tiers = options
+        .trackers
+        .iter()
+        .map(|tier| tier.iter().map(bytes).collect());
+    Ok(Torrent {
+        announce: options.trackers.iter().flatten().next().map(bytes),
+        announce_list: if tracker_count > 1 {
+            Some(tiers.collect())
+        } else {
+            None
+        },
+        creation_date: options.creation_date,
+        comment: options.comment.as_ref().map(bytes),
+        created_by: options.created_by.as_ref().map(bytes),
+        encoding: None,
+        info,
+        piece_layers: if v2 { Some(layers) } else { None },
+        extra,
+    })
+}
+
+struct Source {
+    components: Vec<String>,
+    path: PathBuf,
+    length: u64,
+}
+
+fn utf8_name(path: &Path) -> Result<String, Error> {
+    path.to_str()
+        .map(String::from)
+        .ok_or_else(|| Error::message(format!("`{}` is not valid utf-8", path.display())))
+}
+
+/// collects every regular file below `dir` along with its path components.
+/// links aren't followed, one pointing back up the tree would never end
+fn walk(
+    dir: &Path,
+    components: &mut Vec<String>,
+    files: &mut Vec<(Vec<String>, PathBuf)>,
+) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).map_err(Error::io)? {
+        let entry = entry.map_err(Error::io)?;
+        let path = entry.path();
+        components.push(utf8_name(Path::new(&entry.file_name()))?);
+        let file_type = fs::symlink_metadata(&path).map_err(Error::io)?.file_type();
+        if file_type.is_dir() {
+            walk(&path, components, files)?;
+        } else if file_type.is_file() {
+            files.push((components.clone(), path));
+        }
+        components.pop();
+    }
+    Ok(())
+}
+
+fn new_file(length: u64, path: &[String], attr: Option<&str>) -> File {
+    File {
+        length,
+        path: path.iter().map(bytes).collect(),
+        attr: attr.map(ByteBuf::from),
+        extra: Extra::new(),
+    }
+}
+
+fn bytes(text: &String) -> ByteBuf {
+    ByteBuf::from(text.as_bytes())
+}
+
+fn insert(tree: &mut FileTree, components: &[String], leaf: TreeFile) {
+    match components {
+        [name] => {
+            tree.0.insert(name.clone(), FileTreeNode::File(leaf));
+        }
+        [dir, rest @ ..] => {
+            let node = tree
+                .0
+                .entry(dir.clone())
+                .or_insert_with(|| FileTreeNode::Dir(FileTree::default()));
+            if let FileTreeNode::Dir(subtree) = node {
+                insert(subtree, rest, leaf);
+            }
+        }
+        [] => {}
+    }
+}
+
+/// reads a file in 16 KiB blocks, only the last one shorter, passing each
+/// on to `chunk`. fails if the file doesn't hold exactly `length` bytes, and
+/// never passes on more than that
+pub(crate) fn hash_file(
+    path: &Path,
+    length: u64,
+    mut chunk: impl FnMut(&[u8]),
+) -> Result<(), Error> {
+    let mut file = fs::File::open(path).map_err(Error::io)?;
+    let mut block = vec![0; BLOCK_SIZE as usize];
+    let mut read = 0;
+    loop {
+        let len = read_block(&mut file, &mut block).map_err(Error::io)?;
+        if len == 0 && read == length {
+            return Ok(());
+        }
+        if len == 0 || read + len as u64 > length {
+            return Err(Error::message(format!(
+                "`{}` changed size while being hashed",
+                path.display()
+            )));
+        }
+        read += len as u64;
+        chunk(&block[..len]);
+    }
+}
+
+/// fills `buf` unless the reader runs out first
+fn read_block(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// SHA-1s the data fed to it in pieces, the way v1 torrents hash the
+/// concatenation of all their files
+pub(crate) struct PieceHasher {
+    piece_length: usize,
+    piece: Sha1,
+    filled: usize,
+    pieces: Vec<u8>,
+}
+
+impl PieceHasher {
+    pub(crate) fn new(piece_length: u64) -> Self {
+        PieceHasher {
+            piece_length: piece_length as usize,
+            piece: Sha1::new(),
+            filled: 0,
+            pieces: Vec::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let take = data.len().min(self.piece_length - self.filled);
+            self.piece.update(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == self.piece_length {
+                let piece = std::mem::replace(&mut self.piece, Sha1::new());
+                self.pieces.extend_from_slice(&piece.finalize());
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// feeds `len` zero bytes, a block at a time so that huge padding files
+    /// don't need a buffer of their size
+    pub(crate) fn update_zeros(&mut self, mut len: u64) {
+        let zeros = [0; BLOCK_SIZE as usize];
+        while len > 0 {
+            let take = len.min(BLOCK_SIZE);
+            self.update(&zeros[..take as usize]);
+            len -= take;
+        }
+    }
+
+    /// moves past `len` bytes without reading them, for data that isn't
+    /// there. the pieces they fall into come out with meaningless hashes
+    pub(crate) fn skip(&mut self, mut len: u64) {
+        while len > 0 {
+            let take = len.min((self.piece_length - self.filled) as u64);
+            self.filled += take as usize;
+            len -= take;
+            if self.filled == self.piece_length {
+                self.pieces.extend_from_slice(&[0; 20]);
+                self.piece = Sha1::new();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if self.filled > 0 {
+            self.pieces.extend_from_slice(&self.piece.finalize());
+        }
+        self.pieces
+    }
+}
+
+/// builds a file's BEP 52 merkle tree from its 16 KiB blocks as they are
+/// read. only the block hashes of the current piece are kept, every full
+/// piece is folded into its root right away
+pub(crate) struct MerkleHasher {
+    piece_length: u64,
+    per_piece: usize,
+    blocks: Vec<[u8; 32]>,
+    layer: Vec<u8>,
+}
+
+impl MerkleHasher {
+    pub(crate) fn new(piece_length: u64) -> Self {
+        MerkleHasher {
+            piece_length,
+            per_piece: (piece_length / BLOCK_SIZE) as usize,
+            blocks: Vec::new(),
+            layer: Vec::new(),
+        }
+    }
+
+    pub(crate) fn update(&mut self, block: &[u8]) {
+        // a full piece is only folded once the next block shows up, a file
+        // of a single piece has no layer and takes its root from the blocks
+        if self.blocks.len() == self.per_piece {
+            self.fold_piece();
+        }
+        self.blocks.push(Sha256::digest(block).into());
+    }
+
+    fn fold_piece(&mut self) {
+        let blocks = std::mem::take(&mut self.blocks);
+        let piece = merkle_root(blocks, self.per_piece, [0; 32]);
+        self.layer.extend_from_slice(&piece);
+    }
+
+    /// the root along with the piece layer, which is empty for files no
+    /// bigger than a piece. leaves past the end of the file count as all
+    /// zero hashes
+    pub(crate) fn finish(mut self) -> ([u8; 32], Vec<u8>) {
+        if self.layer.is_empty() {
+            let width = self.blocks.len().next_power_of_two();
+            return (merkle_root(self.blocks, width, [0; 32]), Vec::new());
+        }
+        self.fold_piece();
+        (layer_root(&self.layer, self.piece_length), self.layer)
+    }
+}
+
+/// the root a piece layer hashes up to, which has to be the file's
+/// `pieces root` for the layer to be trusted
+pub(crate) fn layer_root(layer: &[u8], piece_length: u64) -> [u8; 32] {
+    let pieces: Vec<[u8; 32]> = layer
+        .chunks_exact(32)
+        .map(|piece| {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(piece);
+            hash
+        })
+        .collect();
+    // a piece beyond the end is the root of a subtree of zero leaves
+    let mut pad = [0; 32];
+    for _ in 0..(piece_length / BLOCK_SIZE).trailing_zeros() {
+        pad = hash_pair(&pad, &pad);
+    }
+    let width = pieces.len().next_power_of_two();
+    merkle_root(pieces, width, pad)
+}
+
+/// root of a tree `width` nodes wide, filled up with `pad`. the padding is
+/// hashed once per level rather than laid out, `width` comes from the piece
+/// length and can be huge
+fn merkle_root(mut layer: Vec<[u8; 32]>, mut width: usize, mut pad: [u8; 32]) -> [u8; 32] {
+    if layer.is_empty() {
+        layer.push(pad);
+    }
+    while width > 1 {
+        if layer.len() % 2 == 1 {
+            layer.push(pad);
+        }
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        pad = hash_pair(&pad, &pad);
+        width /= 2;
+    }
+    layer[0]
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// an empty scratch directory for a test, unique to the process
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bencode-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8 ^ seed).collect()
+}
+
+#[test]
+fn test_create() {
+    let dir = temp_dir("create");
+    let root = dir.join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    let a = pattern(40000, 1);
+    let b = pattern(3, 2);
+    fs::write(root.join("sub").join("b"), &b).unwrap();
+    fs::write(root.join("a"), &a).unwrap();
+    fs::write(root.join("empty"), b"").unwrap();
+    // would recurse forever if links were followed
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&root, root.join("sub").join("loop")).unwrap();
+
+    let options = CreateOptions {
+        piece_length: Some(16384),
+        trackers: vec![
+            vec!["http://a/announce".into()],
+            vec!["http://b/announce".into()],
+        ],
+        web_seeds: vec!["http://seed/".into()],
+        private: true,
+        source: Some("internal".into()),
+        ..CreateOptions::default()
+    };
+    let torrent = create_torrent(&root, &options).unwrap();
+    torrent.validate().unwrap();
+    assert_eq!(torrent.announce_str(), Some("http://a/announce"));
+    assert_eq!(torrent.trackers().len(), 2);
+    assert_eq!(torrent.info.name_str(), Some("data"));
+    assert_eq!(torrent.info.private, Some(true));
+    assert_eq!(
+        torrent.info.file_list(),
+        [
+            (vec![&b"data"[..], b"a"], 40000),
+            (vec![&b"data"[..], b"empty"], 0),
+            (vec![&b"data"[..], b"sub", b"b"], 3)
+        ]
+    );
+    let mut all = a.clone();
+    all.extend_from_slice(&b);
+    let expected: Vec<u8> = all
+        .chunks(16384)
+        .flat_map(|p| Sha1::digest(p).to_vec())
+        .collect();
+    assert_eq!(torrent.info.pieces.as_deref(), Some(&expected[..]));
+
+    // the encoding round-trips and the options land where they belong
+    let encoded = torrent.to_bytes().unwrap();
+    let decoded = Torrent::from_bytes(&encoded).unwrap();
+    assert_eq!(decoded, torrent);
+    assert_eq!(
+        decoded.info.extra[&ByteBuf::from("source")].as_str(),
+        Some("internal")
+    );
+    assert!(decoded.extra.contains_key(&ByteBuf::from("url-list")));
+    assert_eq!(
+        create_torrent(&root, &options).unwrap().to_bytes().unwrap(),
+        encoded
+    );
+
+    let hybrid = CreateOptions {
+        version: Version::Hybrid,
+        piece_length: Some(16384),
+        ..CreateOptions::default()
+    };
+    let torrent = create_torrent(&root, &hybrid).unwrap();
+    torrent.validate().unwrap();
+    assert_eq!(torrent.info.version(), Version::Hybrid);
+    let files = torrent.info.files.as_ref().unwrap();
+    assert_eq!(files.len(), 4);
+    assert!(files[1].is_padding());
+    assert_eq!(files[1].length, 16384 - 40000 % 16384);
+    assert_eq!(torrent.piece_layers.as_ref().unwrap().len(), 1);
+
+    // 40000 bytes is three blocks, one per piece, under a four leaf tree
+    let blocks: Vec<[u8; 32]> = a.chunks(16384).map(|b| Sha256::digest(b).into()).collect();
+    let root_hash = hash_pair(
+        &hash_pair(&blocks[0], &blocks[1]),
+        &hash_pair(&blocks[2], &[0; 32]),
+    );
+    let tree = torrent.info.file_tree.as_ref().unwrap();
+    match &tree.0["a"] {
+        FileTreeNode::File(file) => assert_eq!(*file.pieces_root.unwrap(), root_hash),
+        node => panic!("{:?}", node),
+    }
+    match &tree.0["sub"] {
+        FileTreeNode::Dir(sub) => match &sub.0["b"] {
+            FileTreeNode::File(file) => {
+                assert_eq!(
+                    *file.pieces_root.unwrap(),
+                    <[u8; 32]>::from(Sha256::digest(&b))
+                )
+            }
+            node => panic!("{:?}", node),
+        },
+        node => panic!("{:?}", node),
+    }
+
+    let single = CreateOptions {
+        version: Version::V2,
+        ..CreateOptions::default()
+    };
+    let torrent = create_torrent(&root.join("a"), &single).unwrap();
+    torrent.validate().unwrap();
+    assert!(torrent.info.is_single_file());
+    assert_eq!(torrent.info.piece_length, 16384);
+    assert_eq!(torrent.info.file_list(), [(vec![&b"a"[..]], 40000)]);
+
+    let bad = CreateOptions {
+        version: Version::V2,
+        piece_length: Some(20000),
+        ..CreateOptions::default()
+    };
+    assert!(create_torrent(&root, &bad).is_err());
+    assert!(create_torrent(&dir.join("missing"), &CreateOptions::default()).is_err());
+    fs::create_dir(dir.join("nothing")).unwrap();
+    assert!(create_torrent(&dir.join("nothing"), &CreateOptions::default()).is_err());
+
+    // a file that isn't the length it was listed with
+    let path = root.join("a");
+    let mut blocks = 0;
+    hash_file(&path, 40000, |_| blocks += 1).unwrap();
+    assert_eq!(blocks, 3);
+    let mut fed = 0;
+    assert!(hash_file(&path, 39999, |chunk| fed += chunk.len()).is_err());
+    assert!(fed <= 39999);
+    assert!(hash_file(&path, 40001, |_| {}).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_merkle_hasher() {
+    // the whole tree laid out at once, the way BEP 52 describes it
+    fn naive(blocks: &[[u8; 32]], width: usize) -> [u8; 32] {
+        let mut layer = blocks.to_vec();
+        layer.resize(width, [0; 32]);
+        while layer.len() > 1 {
+            layer = layer.chunks(2).map(|p| hash_pair(&p[0], &p[1])).collect();
+        }
+        layer[0]
+    }
+
+    let data = pattern(16384 * 9 + 100, 3);
+    let piece_length = 4 * 16384;
+    for &len in [1, 16384, 16385, 4 * 16384, 4 * 16384 + 1, data.len()].iter() {
+        let data = &data[..len];
+        let blocks: Vec<[u8; 32]> = data
+            .chunks(16384)
+            .map(|b| Sha256::digest(b).into())
+            .collect();
+        let mut hasher = MerkleHasher::new(piece_length);
+        data.chunks(16384).for_each(|block| hasher.update(block));
+        let (root, layer) = hasher.finish();
+        if blocks.len() <= 4 {
+            assert_eq!(root, naive(&blocks, blocks.len().next_power_of_two()));
+            assert!(layer.is_empty());
+        } else {
+            let pieces = blocks.len().div_ceil(4);
+            let width = pieces.next_power_of_two() * 4;
+            assert_eq!(root, naive(&blocks, width), "{}", len);
+            let expected: Vec<u8> = blocks.chunks(4).flat_map(|p| naive(p, 4)).collect();
+            assert_eq!(layer, expected);
+        }
+    }
+}
+
+#[test]
+fn test_auto_piece_length() {
+    assert_eq!(auto_piece_length(0), 16384);
+    assert_eq!(auto_piece_length(1 << 30), 1 << 20);
+    assert_eq!(auto_piece_length(1 << 40), 16 << 20);
+}
+
+#[test]
+fn test_piece_hasher() {
+    for &len in [0, 1, 16384, 16385, 50000].iter() {
+        let mut zeros = PieceHasher::new(20000);
+        zeros.update(b"abc");
+        zeros.update_zeros(len);
+        let mut buffered = PieceHasher::new(20000);
+        buffered.update(b"abc");
+        buffered.update(&vec![0; len as usize]);
+        assert_eq!(zeros.finish(), buffered.finish());
+    }
+}
@@ -0,0 +1,382 @@
+/// checking data on disk against a torrent, like a client's resume check
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    create::{hash_file, MerkleHasher, PieceHasher},
+    Torrent, Version,
+};
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// whether each v1 piece hashed correctly, empty for v2-only torrents
+    pub pieces: Vec<bool>,
+    /// every file except padding, in torrent order
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReport {
+    /// below the base directory, starting with the torrent name
+    pub path: PathBuf,
+    pub length: u64,
+    pub status: FileStatus,
+    /// pieces of this file that checked out. those are the file's own v2
+    /// pieces when the torrent has a file tree, otherwise the v1 pieces it
+    /// overlaps, which can be shared with the files next to it
+    pub good_pieces: usize,
+    pub total_pieces: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Complete,
+    /// some pieces don't match
+    Mismatch,
+    Missing,
+    /// on disk with a different length, so it isn't read at all
+    WrongLength(u64),
+    /// there but couldn't be read to the end, say for lack of permission, a
+    /// directory in its place or the file changing size while it was read
+    Unreadable,
+}
+
+impl Verification {
+    pub fn is_complete(&self) -> bool {
+        self.files
+            .iter()
+            .all(|file| file.status == FileStatus::Complete)
+    }
+}
+
+/// a file as laid out in the torrent's data, with the v2 bits if any
+struct Entry {
+    path: PathBuf,
+    length: u64,
+    padding: bool,
+    root: Option<[u8; 32]>,
+}
+
+/// hashes the torrent's files below `base` and compares them with its v1
+/// piece hashes and v2 merkle trees. files that are missing, the wrong
+/// length or unreadable get that status and none of their pieces count as
+/// good. errors are only returned for a torrent that doesn't validate or has
+/// paths that would leave `base`
+pub fn verify(torrent: &Torrent, base: &Path) -> Result<Verification, Error> {
+    torrent.validate()?;
+    let info = &torrent.info;
+    let version = info.version();
+    let piece_length = info.piece_length;
+    let entries = entries(torrent)?;
+
+    // v2-only torrents have no v1 pieces to check, so no SHA-1 at all
+    let mut hasher = if version != Version::V2 {
+        Some(PieceHasher::new(piece_length))
+    } else {
+        None
+    };
+    let mut absent = vec![false; info.piece_count()];
+    let mut offset = 0;
+    let mut files = Vec::new();
+    for entry in entries {
+        let pieces = if entry.length == 0 {
+            0..0
+        } else {
+            offset / piece_length..(offset + entry.length - 1) / piece_length + 1
+        };
+        offset += entry.length;
+        if entry.padding {
+            if let Some(hasher) = &mut hasher {
+                hasher.update_zeros(entry.length);
+            }
+            continue;
+        }
+
+        let full_path = base.join(&entry.path);
+        let mut status = match fs::metadata(&full_path) {
+            Ok(meta) if meta.is_dir() => FileStatus::Unreadable,
+            Ok(meta) if meta.len() == entry.length => FileStatus::Complete,
+            Ok(meta) => FileStatus::WrongLength(meta.len()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => FileStatus::Missing,
+            Err(_) => FileStatus::Unreadable,
+        };
+        let mut read = 0;
+        let mut tree = None;
+        if status == FileStatus::Complete {
+            let mut tree_hasher = match entry.root {
+                Some(_) if version != Version::V1 => Some(MerkleHasher::new(piece_length)),
+                _ => None,
+            };
+            let hashed = hash_file(&full_path, entry.length, |block| {
+                read += block.len() as u64;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(block);
+                }
+                if let Some(tree_hasher) = &mut tree_hasher {
+                    tree_hasher.update(block);
+                }
+            });
+            match hashed {
+                Ok(()) => tree = tree_hasher.map(MerkleHasher::finish),
+                Err(_) => status = FileStatus::Unreadable,
+            }
+        }
+        if status != FileStatus::Complete {
+            // keep the hasher in step with the data for the files after this
+            if let Some(hasher) = &mut hasher {
+                hasher.skip(entry.length - read);
+            }
+            for piece in pieces.clone() {
+                if let Some(absent) = absent.get_mut(piece as usize) {
+                    *absent = true;
+                }
+            }
+        }
+
+        let v2_pieces = match entry.root {
+            Some(root) if version != Version::V1 => Some(check_v2(torrent, &entry, root, tree)),
+            _ if version != Version::V1 => Some(Vec::new()),
+            _ => None,
+        };
+        files.push((entry, status, pieces, v2_pieces));
+    }
+
+    let computed = hasher.map(PieceHasher::finish).unwrap_or_default();
+    let pieces: Vec<bool> = info
+        .piece_hashes()
+        .zip(computed.chunks(20))
+        .zip(&absent)
+        .map(|((expected, computed), absent)| !absent && expected == computed)
+        .collect();
+
+    let files = files
+        .into_iter()
+        .map(|(entry, status, range, v2_pieces)| {
+            let checked = match v2_pieces {
+                Some(v2) => v2,
+                None => range.map(|piece| pieces[piece as usize]).collect(),
+            };
+            let good_pieces = checked.iter().filter(|&&good| good).count();
+            let status = match status {
+                FileStatus::Complete if good_pieces < checked.len() => FileStatus::Mismatch,
+                status => status,
+            };
+            FileReport {
+                path: entry.path,
+                length: entry.length,
+                status,
+                good_pieces,
+                total_pieces: checked.len(),
+            }
+        })
+        .collect();
+    Ok(Verification { pieces, files })
+}
+
+/// the v2 pieces of one file, checked against its piece layer or, for files
+/// no bigger than a piece, straight against its root. `Torrent::validate`
+/// already made sure the layer hashes up to the root. `tree` is the root and
+/// layer of the data on disk, `None` if it couldn't be read in full
+fn check_v2(
+    torrent: &Torrent,
+    entry: &Entry,
+    root: [u8; 32],
+    tree: Option<([u8; 32], Vec<u8>)>,
+) -> Vec<bool> {
+    let piece_length = torrent.info.piece_length;
+    let count = entry.length.div_ceil(piece_length) as usize;
+    let (computed_root, layer) = match tree {
+        Some(tree) => tree,
+        None => return vec![false; count],
+    };
+    if entry.length <= piece_length {
+        return vec![computed_root == root];
+    }
+    let expected = torrent
+        .piece_layers
+        .as_ref()
+        .and_then(|layers| layers.get(&serde_bytes::ByteArray::new(root)));
+    match expected {
+        Some(expected) => expected
+            .chunks(32)
+            .zip(layer.chunks(32))
+            .map(|(expected, computed)| expected == computed)
+            .collect(),
+        None => vec![computed_root == root; count],
+    }
+}
+
+/// the files in data order, v1 padding included, with their v2 roots
+fn entries(torrent: &Torrent) -> Result<Vec<Entry>, Error> {
+    let info = &torrent.info;
+    let roots: Vec<Option<[u8; 32]>> = match &info.file_tree {
+        Some(tree) => tree
+            .files()
+            .into_iter()
+            .map(|(_, file)| file.pieces_root.map(|root| *root))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let files: Vec<(Vec<&[u8]>, u64, bool)> = match info.version() {
+        Version::V2 => info
+            .v2_file_list()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(path, length)| (path, length, false))
+            .collect(),
+        _ => info.v1_files(),
+    };
+
+    // hybrids list the same files in both, validation made sure of that
+    let mut roots = roots.into_iter();
+    files
+        .into_iter()
+        .map(|(components, length, padding)| {
+            let root = if padding {
+                None
+            } else {
+                roots.next().flatten()
+            };
+            Ok(Entry {
+                path: safe_path(&components)?,
+                length,
+                padding,
+                root,
+            })
+        })
+        .collect()
+}
+
+/// joins path components from the torrent, refusing any that could point
+/// outside the base directory
+fn safe_path(components: &[&[u8]]) -> Result<PathBuf, Error> {
+    let mut path = PathBuf::new();
+    for &component in components {
+        let bad = component.is_empty()
+            || component == b"."
+            || component == b".."
+            || component.contains(&b'/')
+            || component.contains(&b'\\')
+            || component.contains(&0);
+        if bad {
+            return Err(Error::message(format!(
+                "unsafe path component `{}` in torrent",
+                String::from_utf8_lossy(component)
+            )));
+        }
+        path.push(file_name(component)?);
+    }
+    Ok(path)
+}
+
+/// unix takes any bytes as a file name, elsewhere they have to be utf-8
+#[cfg(unix)]
+fn file_name(component: &[u8]) -> Result<&OsStr, Error> {
+    Ok(std::os::unix::ffi::OsStrExt::from_bytes(component))
+}
+
+#[cfg(not(unix))]
+fn file_name(component: &[u8]) -> Result<&OsStr, Error> {
+    std::str::from_utf8(component).map(OsStr::new).map_err(|_| {
+        Error::message(format!(
+            "path component `{}` isn't utf-8",
+            String::from_utf8_lossy(component)
+        ))
+    })
+}
+
+#[test]
+fn test_verify() {
+    use super::{create::temp_dir, create_torrent, CreateOptions};
+    use serde_bytes::ByteBuf;
+
+    let dir = temp_dir("verify");
+    let root = dir.join("data");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    let a: Vec<u8> = (0..40000).map(|i| (i % 251) as u8).collect();
+    let b: Vec<u8> = (0..20000).map(|i| (i % 13) as u8).collect();
+    fs::write(root.join("a"), &a).unwrap();
+    fs::write(root.join("sub").join("b"), &b).unwrap();
+    fs::write(root.join("c"), b"tiny").unwrap();
+
+    for version in [Version::V1, Version::V2, Version::Hybrid].iter() {
+        let options = CreateOptions {
+            version: *version,
+            piece_length: Some(16384),
+            ..CreateOptions::default()
+        };
+        let torrent = create_torrent(&root, &options).unwrap();
+
+        let report = verify(&torrent, &dir).unwrap();
+        assert!(report.is_complete(), "{:?} {:?}", version, report);
+        assert!(report.pieces.iter().all(|&good| good));
+        assert_eq!(report.pieces.is_empty(), *version == Version::V2);
+        let paths: Vec<_> = report.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("data/a"),
+                Path::new("data/c"),
+                Path::new("data/sub/b")
+            ]
+        );
+
+        // corrupt the second block of `a`, which is the second piece
+        let mut corrupt = a.clone();
+        corrupt[20000] ^= 0xff;
+        fs::write(root.join("a"), &corrupt).unwrap();
+        let report = verify(&torrent, &dir).unwrap();
+        assert!(!report.is_complete());
+        assert_eq!(report.files[0].status, FileStatus::Mismatch);
+        assert_eq!(report.files[0].good_pieces, 2);
+        assert_eq!(report.files[0].total_pieces, 3);
+        assert_eq!(report.files[2].status, FileStatus::Complete);
+        if *version != Version::V2 {
+            assert!(!report.pieces[1]);
+            assert_eq!(report.pieces.iter().filter(|&&good| !good).count(), 1);
+        }
+        fs::write(root.join("a"), &a).unwrap();
+
+        fs::rename(root.join("c"), dir.join("c")).unwrap();
+        let report = verify(&torrent, &dir).unwrap();
+        assert_eq!(report.files[1].status, FileStatus::Missing);
+        assert_eq!(report.files[1].good_pieces, 0);
+        // without padding `c` shares a piece with the end of `a` and the
+        // start of `b`, which pulls both of them down with it
+        let (status, good) = match version {
+            Version::V1 => (FileStatus::Mismatch, 2),
+            _ => (FileStatus::Complete, 3),
+        };
+        assert_eq!(report.files[0].status, status);
+        assert_eq!(report.files[0].good_pieces, good);
+        assert_eq!(report.files[2].status, status);
+
+        // a directory can't be read as the file, which is reported rather
+        // than failing the whole check
+        fs::create_dir(root.join("c")).unwrap();
+        let report = verify(&torrent, &dir).unwrap();
+        assert_eq!(report.files[1].status, FileStatus::Unreadable);
+        assert_eq!(report.files[1].good_pieces, 0);
+        assert_eq!(report.files[0].status, status);
+        assert_eq!(report.files[2].status, status);
+        fs::remove_dir(root.join("c")).unwrap();
+        fs::rename(dir.join("c"), root.join("c")).unwrap();
+
+        fs::write(root.join("sub").join("b"), &b[1..]).unwrap();
+        let report = verify(&torrent, &dir).unwrap();
+        assert_eq!(report.files[2].status, FileStatus::WrongLength(19999));
+        assert_eq!(report.files[2].good_pieces, 0);
+        fs::write(root.join("sub").join("b"), &b).unwrap();
+    }
+
+    let mut torrent = create_torrent(&root, &CreateOptions::default()).unwrap();
+    torrent.info.files.as_mut().unwrap()[0].path = vec![ByteBuf::from(".."), ByteBuf::from("a")];
+    assert!(verify(&torrent, &dir).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,189 @@
+/// verbatim slices of a document, for hashing or passing along untouched
+use std::fmt;
+
+use serde::{
+    de::{self, IgnoredAny},
+    ser, Deserialize, Serialize,
+};
+
+use crate::{decode, Error};
+
+/// the newtype name that tells `decode::Deserializer` to capture the span of
+/// the next value and `encode::Serializer` to write one back unchanged
+pub(crate) const TOKEN: &str = "$bencode::private::RawValue";
+
+/// the exact bytes of one encoded value, borrowed from the input.
+/// only `decode::Deserializer` can produce these, other deserializers
+/// don't know where their values came from
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawValue<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RawValue<'a> {
+    /// checks that `bytes` is exactly one well formed value
+    pub fn new(bytes: &'a [u8]) -> Result<Self, Error> {
+        decode::from_bytes::<IgnoredAny>(bytes)?;
+        Ok(RawValue { bytes })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// decodes the captured value now that its shape is known
+    pub fn decode<T: Deserialize<'a>>(&self) -> Result<T, Error> {
+        decode::from_bytes(self.bytes)
+    }
+
+    pub fn to_owned(&self) -> RawValueBuf {
+        RawValueBuf {
+            bytes: self.bytes.to_vec(),
+        }
+    }
+}
+
+/// owned version of `RawValue`, which is what decoding from a reader gives
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct RawValueBuf {
+    bytes: Vec<u8>,
+}
+
+impl RawValueBuf {
+    /// checks that `bytes` is exactly one well formed value
+    pub fn new(bytes: Vec<u8>) -> Result<Self, Error> {
+        decode::from_bytes::<IgnoredAny>(&bytes)?;
+        Ok(RawValueBuf { bytes })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub fn as_raw_value(&self) -> RawValue<'_> {
+        RawValue { bytes: &self.bytes }
+    }
+
+    pub fn decode<'a, T: Deserialize<'a>>(&'a self) -> Result<T, Error> {
+        decode::from_bytes(&self.bytes)
+    }
+}
+
+impl fmt::Debug for RawValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RawValue({:?})", String::from_utf8_lossy(self.bytes))
+    }
+}
+
+impl fmt::Debug for RawValueBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RawValueBuf({:?})", String::from_utf8_lossy(&self.bytes))
+    }
+}
+
+// ---- SERDE ----
+
+impl Serialize for RawValue<'_> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_newtype_struct(TOKEN, serde_bytes::Bytes::new(self.bytes))
+    }
+}
+
+impl Serialize for RawValueBuf {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_raw_value().serialize(serializer)
+    }
+}
+
+struct RawVisitor;
+
+impl<'de> de::Visitor<'de> for RawVisitor {
+    type Value = &'de [u8];
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a raw value borrowed from the input")
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<&'de [u8], E> {
+        Ok(v)
+    }
+}
+
+struct RawBufVisitor;
+
+impl<'de> de::Visitor<'de> for RawBufVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a raw value")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+        Ok(v)
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawValue<'a> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_newtype_struct(TOKEN, RawVisitor)?;
+        Ok(RawValue { bytes })
+    }
+}
+
+impl<'de> Deserialize<'de> for RawValueBuf {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserializer.deserialize_newtype_struct(TOKEN, RawBufVisitor)?;
+        Ok(RawValueBuf { bytes })
+    }
+}
+
+#[test]
+fn test_raw_value() {
+    use crate::{decode::from_reader, encode::to_bytes, Value};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Torrent<'a> {
+        announce: String,
+        #[serde(borrow)]
+        info: RawValue<'a>,
+    }
+
+    // unsorted keys inside `info` have to survive untouched
+    let info = b"d6:lengthi5e4:name1:a12:piece lengthi16384e6:pieces0:1:bi1ee";
+    let mut data = b"d8:announce3:url4:info".to_vec();
+    data.extend_from_slice(info);
+    data.push(b'e');
+
+    let torrent: Torrent = decode::from_bytes(&data).unwrap();
+    assert_eq!(torrent.info.as_bytes(), &info[..]);
+    assert_eq!(to_bytes(&torrent).unwrap(), data);
+    let value: Value = torrent.info.decode().unwrap();
+    assert_eq!(value["name"].as_str(), Some("a"));
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Owned {
+        announce: String,
+        info: RawValueBuf,
+    }
+    let owned: Owned = from_reader(&data[..]).unwrap();
+    assert_eq!(owned.info.as_bytes(), &info[..]);
+    assert_eq!(owned.info, torrent.info.to_owned());
+    assert_eq!(to_bytes(&owned).unwrap(), data);
+
+    let list: Vec<RawValue> = decode::from_bytes(b"li1e3:abcle0:e").unwrap();
+    let spans: Vec<&[u8]> = list.iter().map(RawValue::as_bytes).collect();
+    assert_eq!(spans, [&b"i1e"[..], b"3:abc", b"le", b"0:"]);
+
+    assert!(RawValue::new(b"i1e").is_ok());
+    assert!(RawValue::new(b"i1ei2e").is_err());
+    assert!(RawValueBuf::new(b"d1:a".to_vec()).is_err());
+    assert!(decode::from_bytes::<RawValue>(b"d1:ae").is_err());
+}
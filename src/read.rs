@@ -0,0 +1,354 @@
+/// input sources the decode::Deserializer pulls its tokens from
+use std::{
+    io::{self, Read as _},
+    ops::Deref,
+};
+
+use nom::{
+    bytes::streaming::{tag, take, take_until1},
+    character::streaming::digit1,
+    sequence::{delimited, terminated},
+    IResult,
+};
+
+use crate::{Error, ErrorKind};
+
+//
+// ------------------------------- NOM -------------------------------
+//
+
+fn int(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    delimited(tag("i"), take_until1("e"), tag("e"))(i)
+}
+
+fn str_len(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    terminated(digit1, tag(":"))(i)
+}
+
+/// printable form of a byte for error messages
+pub(crate) fn describe(byte: u8) -> String {
+    if byte.is_ascii_graphic() {
+        format!("'{}'", byte as char)
+    } else {
+        format!("byte 0x{:02x}", byte)
+    }
+}
+
+//
+// ------------------------------- READ -------------------------------
+//
+
+/// digits in `u64::MAX`, no integer or length that fits is any longer
+const MAX_DIGITS: usize = 20;
+
+/// bytes that are either borrowed from the input or copied into a scratch buffer
+pub enum Reference<'b, 'c> {
+    Borrowed(&'b [u8]),
+    Copied(&'c [u8]),
+}
+
+impl Deref for Reference<'_, '_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Reference::Borrowed(b) => b,
+            Reference::Copied(c) => c,
+        }
+    }
+}
+
+/// sealed so the token methods can change without breaking anyone
+pub trait Read<'de>: private::Sealed {
+    /// next byte without consuming it, `None` once the input is exhausted
+    fn peek(&mut self) -> Result<Option<u8>, Error>;
+
+    /// consumes the byte returned by the last `peek`
+    fn discard(&mut self);
+
+    /// number of bytes consumed so far
+    fn position(&self) -> usize;
+
+    /// the text between `i` and `e` of an integer
+    fn parse_int<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>, Error>;
+
+    /// the decimal length prefix of a byte string, consuming the `:`
+    fn parse_str_len<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's>, Error>;
+
+    /// the next `len` bytes
+    fn parse_bytes<'s>(
+        &'s mut self,
+        len: usize,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's>, Error>;
+
+    /// starts recording the bytes consumed from here on
+    fn begin_raw(&mut self);
+
+    /// stops recording and returns everything consumed since `begin_raw`
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'de, 's>;
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+pub struct SliceRead<'a> {
+    slice: &'a [u8],
+    index: usize,
+    raw_start: usize,
+}
+
+impl<'a> SliceRead<'a> {
+    pub fn new(slice: &'a [u8]) -> Self {
+        SliceRead {
+            slice,
+            index: 0,
+            raw_start: 0,
+        }
+    }
+
+    /// moves past a parsed token, running out of input mid-token is an eof
+    /// and anything else is `kind` unless the very first byte was wrong
+    fn advance<T>(
+        &mut self,
+        res: IResult<&'a [u8], T>,
+        kind: ErrorKind,
+        expected: &'static str,
+        lead: fn(&u8) -> bool,
+    ) -> Result<T, Error> {
+        match res {
+            Ok((rest, out)) => {
+                self.index = self.slice.len() - rest.len();
+                Ok(out)
+            }
+            Err(nom::Err::Incomplete(_)) => {
+                Err(Error::new(ErrorKind::UnexpectedEof).expected(expected))
+            }
+            Err(_) => {
+                let byte = self.slice[self.index];
+                if lead(&byte) {
+                    Err(Error::new(kind).expected(expected))
+                } else {
+                    Err(Error::new(ErrorKind::UnexpectedToken)
+                        .expected(expected)
+                        .found(describe(byte)))
+                }
+            }
+        }
+    }
+}
+
+impl private::Sealed for SliceRead<'_> {}
+
+impl<'a> Read<'a> for SliceRead<'a> {
+    fn peek(&mut self) -> Result<Option<u8>, Error> {
+        Ok(self.slice.get(self.index).copied())
+    }
+
+    fn discard(&mut self) {
+        self.index += 1;
+    }
+
+    fn position(&self) -> usize {
+        self.index
+    }
+
+    fn parse_int<'s>(&'s mut self, _scratch: &'s mut Vec<u8>) -> Result<Reference<'a, 's>, Error> {
+        let res = int(&self.slice[self.index..]);
+        self.advance(res, ErrorKind::InvalidInteger, "integer", |&b| b == b'i')
+            .map(Reference::Borrowed)
+    }
+
+    fn parse_str_len<'s>(
+        &'s mut self,
+        _scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'a, 's>, Error> {
+        let res = str_len(&self.slice[self.index..]);
+        self.advance(
+            res,
+            ErrorKind::InvalidLength,
+            "byte string",
+            u8::is_ascii_digit,
+        )
+        .map(Reference::Borrowed)
+    }
+
+    fn parse_bytes<'s>(
+        &'s mut self,
+        len: usize,
+        _scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'a, 's>, Error> {
+        let res = take(len)(&self.slice[self.index..]);
+        self.advance(res, ErrorKind::InvalidLength, "byte string", |_| true)
+            .map(Reference::Borrowed)
+    }
+
+    fn begin_raw(&mut self) {
+        self.raw_start = self.index;
+    }
+
+    fn end_raw<'s>(&'s mut self, _scratch: &'s mut Vec<u8>) -> Reference<'a, 's> {
+        Reference::Borrowed(&self.slice[self.raw_start..self.index])
+    }
+}
+
+/// pulls bytes from a reader one at a time, so wrap unbuffered sources in a
+/// `BufReader`
+pub struct IoRead<R> {
+    reader: R,
+    peeked: Option<u8>,
+    pos: usize,
+    raw: Option<Vec<u8>>,
+}
+
+impl<R: io::Read> IoRead<R> {
+    pub fn new(reader: R) -> Self {
+        IoRead {
+            reader,
+            peeked: None,
+            pos: 0,
+            raw: None,
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<u8>, Error> {
+        let byte = self.peek()?;
+        self.discard();
+        Ok(byte)
+    }
+
+    fn next_or_eof(&mut self) -> Result<u8, Error> {
+        self.next()?
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof))
+    }
+}
+
+impl<R> private::Sealed for IoRead<R> {}
+
+impl<'de, R: io::Read> Read<'de> for IoRead<R> {
+    fn peek(&mut self) -> Result<Option<u8>, Error> {
+        if self.peeked.is_none() {
+            let mut buf = [0];
+            loop {
+                match self.reader.read(&mut buf) {
+                    Ok(0) => return Ok(None),
+                    Ok(_) => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(Error::io(e)),
+                }
+            }
+            self.peeked = Some(buf[0]);
+        }
+        Ok(self.peeked)
+    }
+
+    fn discard(&mut self) {
+        if let Some(byte) = self.peeked.take() {
+            self.pos += 1;
+            if let Some(raw) = &mut self.raw {
+                raw.push(byte);
+            }
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn parse_int<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Result<Reference<'de, 's>, Error> {
+        let eof = |e: Error| e.expected("integer");
+        match self.next_or_eof().map_err(eof)? {
+            b'i' => {}
+            byte => {
+                return Err(Error::new(ErrorKind::UnexpectedToken)
+                    .expected("integer")
+                    .found(describe(byte)))
+            }
+        }
+        scratch.clear();
+        loop {
+            match self.next_or_eof().map_err(eof)? {
+                b'e' if !scratch.is_empty() => return Ok(Reference::Copied(scratch)),
+                b'e' => return Err(Error::new(ErrorKind::InvalidInteger).expected("integer")),
+                // a sign and more digits than fit, stop before buffering the rest
+                _ if scratch.len() > MAX_DIGITS => {
+                    return Err(Error::new(ErrorKind::InvalidInteger)
+                        .expected("integer")
+                        .found("too many digits")
+                        .or_offset(self.pos - 1))
+                }
+                byte => scratch.push(byte),
+            }
+        }
+    }
+
+    fn parse_str_len<'s>(
+        &'s mut self,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's>, Error> {
+        scratch.clear();
+        loop {
+            match self.next_or_eof().map_err(|e| e.expected("byte string"))? {
+                b':' if !scratch.is_empty() => return Ok(Reference::Copied(scratch)),
+                b'0'..=b'9' if scratch.len() == MAX_DIGITS => {
+                    return Err(Error::new(ErrorKind::InvalidLength)
+                        .expected("byte string")
+                        .found("too many digits")
+                        .or_offset(self.pos - 1))
+                }
+                byte @ b'0'..=b'9' => scratch.push(byte),
+                byte if scratch.is_empty() => {
+                    return Err(Error::new(ErrorKind::UnexpectedToken)
+                        .expected("byte string")
+                        .found(describe(byte)))
+                }
+                byte => {
+                    return Err(Error::new(ErrorKind::InvalidLength)
+                        .expected("byte string")
+                        .found(describe(byte)))
+                }
+            }
+        }
+    }
+
+    fn parse_bytes<'s>(
+        &'s mut self,
+        len: usize,
+        scratch: &'s mut Vec<u8>,
+    ) -> Result<Reference<'de, 's>, Error> {
+        scratch.clear();
+        let mut remaining = len;
+        if remaining > 0 {
+            if let Some(byte) = self.peeked.take() {
+                scratch.push(byte);
+                remaining -= 1;
+            }
+        }
+        // grows with the data actually read rather than trusting the length
+        (&mut self.reader)
+            .take(remaining as u64)
+            .read_to_end(scratch)
+            .map_err(Error::io)?;
+        if scratch.len() < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof).expected("byte string"));
+        }
+        self.pos += len;
+        if let Some(raw) = &mut self.raw {
+            raw.extend_from_slice(scratch);
+        }
+        Ok(Reference::Copied(scratch))
+    }
+
+    fn begin_raw(&mut self) {
+        self.raw = Some(Vec::new());
+    }
+
+    fn end_raw<'s>(&'s mut self, scratch: &'s mut Vec<u8>) -> Reference<'de, 's> {
+        *scratch = self.raw.take().unwrap_or_default();
+        Reference::Copied(scratch)
+    }
+}
@@ -0,0 +1,161 @@
+/// values tagged with where they sat in the input
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Deref, DerefMut, Range},
+};
+
+use serde::{de, ser, Deserialize, Serialize};
+
+/// struct name and fields `decode::Deserializer` looks for to fill in a span
+pub(crate) const NAME: &str = "$bencode::private::Spanned";
+pub(crate) const START: &str = "$bencode::private::Spanned::start";
+pub(crate) const VALUE: &str = "$bencode::private::Spanned::value";
+pub(crate) const END: &str = "$bencode::private::Spanned::end";
+
+/// a decoded value along with the byte range it was decoded from, which is
+/// only filled in by `decode::Deserializer`. comparisons and hashing look at
+/// the value alone, and encoding writes just the value
+#[derive(Clone, Debug)]
+pub struct Spanned<T> {
+    start: usize,
+    end: usize,
+    value: T,
+}
+
+impl<T> Spanned<T> {
+    /// offset of the first byte of the value
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// offset just past the last byte of the value
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.value
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: Eq> Eq for Spanned<T> {}
+
+impl<T: Hash> Hash for Spanned<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.value.hash(state)
+    }
+}
+
+// ---- SERDE ----
+
+impl<T: Serialize> Serialize for Spanned<T> {
+    fn serialize<S: ser::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+fn expect_key<'de, A: de::MapAccess<'de>>(map: &mut A, name: &str) -> Result<(), A::Error> {
+    match map.next_key::<&str>()? {
+        Some(key) if key == name => Ok(()),
+        _ => Err(de::Error::custom(
+            "Spanned can only be decoded by bencode::decode::Deserializer",
+        )),
+    }
+}
+
+struct SpannedVisitor<T>(std::marker::PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>> de::Visitor<'de> for SpannedVisitor<T> {
+    type Value = Spanned<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value decoded by bencode::decode::Deserializer")
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Spanned<T>, A::Error> {
+        expect_key(&mut map, START)?;
+        let start = map.next_value()?;
+        expect_key(&mut map, VALUE)?;
+        let value = map.next_value()?;
+        expect_key(&mut map, END)?;
+        let end = map.next_value()?;
+        Ok(Spanned { start, end, value })
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Spanned<T> {
+    fn deserialize<D: de::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_struct(
+            NAME,
+            &[START, VALUE, END],
+            SpannedVisitor(std::marker::PhantomData),
+        )
+    }
+}
+
+#[test]
+fn test_spanned() {
+    use crate::{
+        decode::{from_bytes, from_reader},
+        encode::to_bytes,
+    };
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+    struct Info {
+        length: Spanned<i64>,
+        name: Spanned<String>,
+        files: Spanned<Vec<Spanned<i64>>>,
+    }
+
+    let data = b"d5:filesli1ei22ee6:lengthi300e4:name3:abce";
+    let info: Info = from_bytes(data).unwrap();
+    assert_eq!(*info.length, 300);
+    assert_eq!(&data[info.length.span()], b"i300e");
+    assert_eq!(&data[info.name.span()], b"3:abc");
+    assert_eq!(info.files.span(), 8..17);
+    assert_eq!(info.files[1].span(), 12..16);
+    assert_eq!(to_bytes(&info).unwrap(), data.to_vec());
+    assert_eq!(from_reader::<_, Info>(&data[..]).unwrap().name.start(), 36);
+
+    // patch the name in place, keeping the same length
+    let mut patched = data.to_vec();
+    patched[info.name.span()].copy_from_slice(b"3:xyz");
+    assert_eq!(*from_bytes::<Info>(&patched).unwrap().name, "xyz");
+
+    assert!(from_bytes::<Spanned<i64>>(b"3:abc").is_err());
+    let other = de::value::I64Deserializer::<crate::Error>::new(1);
+    assert!(Spanned::<i64>::deserialize(other).is_err());
+}
@@ -0,0 +1,460 @@
+/// dynamically typed bencode tree for documents of unknown shape
+use std::{collections::BTreeMap, convert::TryFrom, fmt, ops::Index};
+
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    ser::{SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    Dict(BTreeMap<Vec<u8>, Value>),
+}
+
+impl Value {
+    /// looks up a key if this is a dict
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<&Value> {
+        match self {
+            Value::Dict(dict) => dict.get(key.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut<K: AsRef<[u8]>>(&mut self, key: K) -> Option<&mut Value> {
+        match self {
+            Value::Dict(dict) => dict.get_mut(key.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// byte string contents, if they are valid utf-8
+    pub fn as_str(&self) -> Option<&str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Option<&mut Vec<Value>> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict_mut(&mut self) -> Option<&mut BTreeMap<Vec<u8>, Value>> {
+        match self {
+            Value::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+}
+
+/// panics if the value is not a dict or the key is missing, like `BTreeMap`
+impl Index<&str> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &str) -> &Value {
+        self.get(key).expect("key not found in bencode dict")
+    }
+}
+
+impl Index<&[u8]> for Value {
+    type Output = Value;
+
+    fn index(&self, key: &[u8]) -> &Value {
+        self.get(key).expect("key not found in bencode dict")
+    }
+}
+
+/// panics if the value is not a list or the index is out of bounds, like `Vec`
+impl Index<usize> for Value {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        match self {
+            Value::List(list) => &list[index],
+            _ => panic!("cannot index into non-list bencode value"),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(int: i64) -> Self {
+        Value::Int(int)
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(bytes: &[u8]) -> Self {
+        Value::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(str: &str) -> Self {
+        Value::Bytes(str.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for Value {
+    fn from(str: String) -> Self {
+        Value::Bytes(str.into_bytes())
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(list: Vec<Value>) -> Self {
+        Value::List(list)
+    }
+}
+
+impl From<BTreeMap<Vec<u8>, Value>> for Value {
+    fn from(dict: BTreeMap<Vec<u8>, Value>) -> Self {
+        Value::Dict(dict)
+    }
+}
+
+/// borrowed counterpart of `Value`, byte strings and keys point into the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueRef<'a> {
+    Int(i64),
+    Bytes(&'a [u8]),
+    List(Vec<ValueRef<'a>>),
+    Dict(BTreeMap<&'a [u8], ValueRef<'a>>),
+}
+
+impl<'a> ValueRef<'a> {
+    /// looks up a key if this is a dict
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Option<&ValueRef<'a>> {
+        match self {
+            ValueRef::Dict(dict) => dict.get(key.as_ref()),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            ValueRef::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            ValueRef::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// byte string contents, if they are valid utf-8
+    pub fn as_str(&self) -> Option<&'a str> {
+        self.as_bytes().and_then(|b| std::str::from_utf8(b).ok())
+    }
+
+    pub fn as_list(&self) -> Option<&[ValueRef<'a>]> {
+        match self {
+            ValueRef::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    pub fn as_dict(&self) -> Option<&BTreeMap<&'a [u8], ValueRef<'a>>> {
+        match self {
+            ValueRef::Dict(dict) => Some(dict),
+            _ => None,
+        }
+    }
+
+    /// copies every borrowed byte string into an owned `Value`
+    pub fn to_value(&self) -> Value {
+        match self {
+            ValueRef::Int(int) => Value::Int(*int),
+            ValueRef::Bytes(bytes) => Value::Bytes(bytes.to_vec()),
+            ValueRef::List(list) => Value::List(list.iter().map(ValueRef::to_value).collect()),
+            ValueRef::Dict(dict) => Value::Dict(
+                dict.iter()
+                    .map(|(k, v)| (k.to_vec(), v.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'a> Index<&str> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, key: &str) -> &ValueRef<'a> {
+        self.get(key).expect("key not found in bencode dict")
+    }
+}
+
+impl<'a> Index<usize> for ValueRef<'a> {
+    type Output = ValueRef<'a>;
+
+    fn index(&self, index: usize) -> &ValueRef<'a> {
+        match self {
+            ValueRef::List(list) => &list[index],
+            _ => panic!("cannot index into non-list bencode value"),
+        }
+    }
+}
+
+impl From<ValueRef<'_>> for Value {
+    fn from(value: ValueRef<'_>) -> Self {
+        value.to_value()
+    }
+}
+
+impl<'a> From<&'a Value> for ValueRef<'a> {
+    fn from(value: &'a Value) -> Self {
+        match value {
+            Value::Int(int) => ValueRef::Int(*int),
+            Value::Bytes(bytes) => ValueRef::Bytes(bytes),
+            Value::List(list) => ValueRef::List(list.iter().map(ValueRef::from).collect()),
+            Value::Dict(dict) => ValueRef::Dict(
+                dict.iter()
+                    .map(|(k, v)| (k.as_slice(), ValueRef::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+//
+// ------------------------------- SERDE -------------------------------
+//
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Int(int) => serializer.serialize_i64(*int),
+            Value::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Value::List(list) => {
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for item in list {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Dict(dict) => {
+                let mut map = serializer.serialize_map(Some(dict.len()))?;
+                for (k, v) in dict {
+                    map.serialize_entry(serde_bytes::Bytes::new(k), v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a bencode value")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+        Ok(Value::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+        i64::try_from(v)
+            .map(Value::Int)
+            .map_err(|_| E::custom("integer out of range for Value"))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+        Ok(Value::Bytes(v.as_bytes().to_vec()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+        Ok(Value::Bytes(v.into_bytes()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Value, E> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Value, E> {
+        Ok(Value::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut list = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            list.push(item);
+        }
+        Ok(Value::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut dict = BTreeMap::new();
+        while let Some(key) = map.next_key::<Value>()? {
+            let key = match key {
+                Value::Bytes(key) => key,
+                _ => return Err(de::Error::custom("dict key must be a byte string")),
+            };
+            dict.insert(key, map.next_value()?);
+        }
+        Ok(Value::Dict(dict))
+    }
+}
+
+impl Serialize for ValueRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ValueRef::Int(int) => serializer.serialize_i64(*int),
+            ValueRef::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            ValueRef::List(list) => {
+                let mut seq = serializer.serialize_seq(Some(list.len()))?;
+                for item in list {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            ValueRef::Dict(dict) => {
+                let mut map = serializer.serialize_map(Some(dict.len()))?;
+                for (k, v) in dict {
+                    map.serialize_entry(serde_bytes::Bytes::new(k), v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueRef<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueRefVisitor)
+    }
+}
+
+struct ValueRefVisitor;
+
+impl<'de> Visitor<'de> for ValueRefVisitor {
+    type Value = ValueRef<'de>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a bencode value borrowed from the input")
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<ValueRef<'de>, E> {
+        Ok(ValueRef::Int(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<ValueRef<'de>, E> {
+        i64::try_from(v)
+            .map(ValueRef::Int)
+            .map_err(|_| E::custom("integer out of range for ValueRef"))
+    }
+
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<ValueRef<'de>, E> {
+        Ok(ValueRef::Bytes(v.as_bytes()))
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<ValueRef<'de>, E> {
+        Ok(ValueRef::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<ValueRef<'de>, A::Error> {
+        let mut list = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            list.push(item);
+        }
+        Ok(ValueRef::List(list))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ValueRef<'de>, A::Error> {
+        let mut dict = BTreeMap::new();
+        while let Some(key) = map.next_key::<ValueRef>()? {
+            let key = match key {
+                ValueRef::Bytes(key) => key,
+                _ => return Err(de::Error::custom("dict key must be a byte string")),
+            };
+            dict.insert(key, map.next_value()?);
+        }
+        Ok(ValueRef::Dict(dict))
+    }
+}
+
+//
+// ------------------------------- TESTS -------------------------------
+//
+
+#[test]
+fn test_value() {
+    let data = b"d4:infod6:lengthi-12e4:name4:teste4:listli1e2:hiee";
+    let x = crate::decode::from_bytes::<Value>(data).unwrap();
+
+    assert_eq!(x["info"]["length"].as_int(), Some(-12));
+    assert_eq!(x["info"]["name"].as_str(), Some("test"));
+    assert_eq!(x["list"][1].as_bytes(), Some(&b"hi"[..]));
+    assert_eq!(x.get("missing"), None);
+    assert_eq!(x["list"].get("key"), None);
+
+    assert_eq!(crate::encode::to_bytes(&x).unwrap(), &data[..]);
+}
+
+#[test]
+fn test_value_ref() {
+    let data = b"d4:infod6:lengthi12e6:pieces6:abcdefe4:listli1e2:hiee";
+    let x = crate::decode::from_bytes::<ValueRef>(data).unwrap();
+
+    let pieces = x["info"]
+        .get("pieces")
+        .and_then(ValueRef::as_bytes)
+        .unwrap();
+    assert_eq!(pieces, b"abcdef");
+    assert_eq!(pieces.as_ptr(), data[30..].as_ptr());
+    assert_eq!(x["list"][0].as_int(), Some(1));
+
+    let owned = x.to_value();
+    assert_eq!(owned, crate::decode::from_bytes::<Value>(data).unwrap());
+    assert_eq!(ValueRef::from(&owned), x);
+    assert_eq!(crate::encode::to_bytes(&x).unwrap(), &data[..]);
+}
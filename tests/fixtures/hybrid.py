@@ -0,0 +1,89 @@
+"""Writes hybrid.torrent and prints its infohashes.
+
+A standalone bencoder and BEP 52 merkle tree, sharing no code with the
+crate, so the fixture doesn't come out of the code it is used to test.
+"""
+import hashlib
+
+PIECE = 32768
+BLOCK = 16384
+
+
+def bencode(v):
+    if isinstance(v, int):
+        return b"i%de" % v
+    if isinstance(v, bytes):
+        return b"%d:%s" % (len(v), v)
+    if isinstance(v, list):
+        return b"l" + b"".join(map(bencode, v)) + b"e"
+    if isinstance(v, dict):
+        return b"d" + b"".join(bencode(k) + bencode(v[k]) for k in sorted(v)) + b"e"
+    raise TypeError(v)
+
+
+def sha256(data):
+    return hashlib.sha256(data).digest()
+
+
+def root(leaves, width, pad):
+    leaves = leaves + [pad] * (width - len(leaves))
+    while len(leaves) > 1:
+        leaves = [sha256(leaves[i] + leaves[i + 1]) for i in range(0, len(leaves), 2)]
+    return leaves[0]
+
+
+def merkle(data):
+    """pieces root and piece layer of one file"""
+    blocks = [sha256(data[i:i + BLOCK]) for i in range(0, len(data), BLOCK)]
+    per_piece = PIECE // BLOCK
+    if len(blocks) <= per_piece:
+        width = 1 << (len(blocks) - 1).bit_length()
+        return root(blocks, width, bytes(32)), b""
+    pieces = [root(blocks[i:i + per_piece], per_piece, bytes(32))
+              for i in range(0, len(blocks), per_piece)]
+    pad = root([], per_piece, bytes(32))
+    width = 1 << (len(pieces) - 1).bit_length()
+    return root(pieces, width, pad), b"".join(pieces)
+
+
+a = (b"hello bencode\n" * 2000)[:20000]
+b = bytes((i * 7 + 3) % 256 for i in range(70000))
+a_root, _ = merkle(a)
+b_root, b_layer = merkle(b)
+
+# v1 data is `a`, padding up to the next piece, then `b`
+padding = PIECE - len(a) % PIECE
+v1 = a + bytes(padding) + b
+pieces = b"".join(hashlib.sha1(v1[i:i + PIECE]).digest() for i in range(0, len(v1), PIECE))
+
+info = {
+    b"name": b"hybrid-test",
+    b"piece length": PIECE,
+    b"meta version": 2,
+    b"pieces": pieces,
+    b"files": [
+        {b"length": len(a), b"path": [b"a.txt"]},
+        {b"length": padding, b"path": [b".pad", str(padding).encode()], b"attr": b"p"},
+        {b"length": len(b), b"path": [b"sub", b"b.bin"]},
+    ],
+    b"file tree": {
+        b"a.txt": {b"": {b"length": len(a), b"pieces root": a_root}},
+        b"sub": {b"b.bin": {b"": {b"length": len(b), b"pieces root": b_root}}},
+    },
+}
+torrent = {
+    b"announce": b"http://tracker.example/announce",
+    b"comment": b"BEP 52 hybrid fixture",
+    b"created by": b"hybrid.py",
+    b"creation date": 1700000000,
+    b"info": info,
+    b"piece layers": {b_root: b_layer},
+}
+
+with open("hybrid.torrent", "wb") as f:
+    f.write(bencode(torrent))
+encoded = bencode(info)
+print("v1", hashlib.sha1(encoded).hexdigest())
+print("v2", hashlib.sha256(encoded).hexdigest())
+print("a root", a_root.hex())
+print("b root", b_root.hex())